@@ -238,6 +238,11 @@ el_set(EditLine *el, int op, ...)
 		break;
 	}
 
+	case EL_REFRESHFN:
+		el->el_refresh.r_hook = va_arg(va, el_rfunc_t);
+		rv = 0;
+		break;
+
 	case EL_EDITMODE:
 		if (va_arg(va, int))
 			el->el_flags &= ~EDIT_DISABLED;
@@ -255,6 +260,19 @@ el_set(EditLine *el, int op, ...)
 }
 
 
+/* el_refreshfn_op():
+ *	The el_set() op for the refresh hook. Other libedits have no
+ *	hook and may use the same number for something else, so callers
+ *	that might be linked against one look for this symbol first.
+ */
+public int
+el_refreshfn_op(void)
+{
+
+	return (EL_REFRESHFN);
+}
+
+
 /* el_get():
  *	retrieve the editline parameters
  */
@@ -107,6 +107,7 @@ int		 el_parse(EditLine *, int, char **);
  * Low level editline access functions
  */
 int		 el_set(EditLine *, int, ...);
+int		 el_refreshfn_op(void);
 int		 el_get(EditLine *, int, void *);
 
 /*
@@ -126,6 +127,15 @@ int		 el_get(EditLine *, int, void *);
 #define	EL_HIST		10	/* , hist_fun_t, const char *);	*/
 #define	EL_EDITMODE	11	/* , int);			*/
 #define	EL_RPROMPT	12	/* , el_pfunc_t);		*/
+#define	EL_REFRESHFN	13	/* , el_rfunc_t);		*/
+
+/*
+ * Refresh hook: called after the input line has been redrawn. It may
+ * return the line text decorated with terminal escapes (e.g. colors),
+ * which is then written over the plain text; the visible characters
+ * must be exactly those of the buffer. Return NULL to leave it plain.
+ */
+typedef const char *(*el_rfunc_t)(EditLine *);
 
 /*
  * Source named file or $PWD/.editrc or $HOME/.editrc
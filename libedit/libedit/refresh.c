@@ -63,6 +63,7 @@ private void	re_delete(EditLine *, char *, int, int, int);
 private void	re_fastputc(EditLine *, int);
 private void	re__strncopy(char *, char *, size_t);
 private void	re__copy_and_pad(char *, char *, size_t);
+private void	re_highlight(EditLine *, coord_t *, coord_t *, coord_t *);
 
 #ifdef DEBUG_REFRESH
 private void	re_printstr(EditLine *, char *, char *, char *);
@@ -191,7 +192,7 @@ re_refresh(EditLine *el)
 {
 	int i, rhdiff;
 	char *cp, *st;
-	coord_t cur;
+	coord_t cur, start, end;
 #ifdef notyet
 	size_t termsz;
 #endif
@@ -214,6 +215,7 @@ re_refresh(EditLine *el)
 	cur.v = 0;
 
 	prompt_print(el, EL_PROMPT);
+	start = el->el_refresh.r_cursor;
 
 	/* draw the current input buffer */
 #if notyet
@@ -245,6 +247,7 @@ re_refresh(EditLine *el)
 		cur.h = el->el_refresh.r_cursor.h;
 		cur.v = el->el_refresh.r_cursor.v;
 	}
+	end = el->el_refresh.r_cursor;
 	rhdiff = el->el_term.t_size.h - el->el_refresh.r_cursor.h -
 	    el->el_rprompt.p_pos.h;
 	if (el->el_rprompt.p_pos.h && !el->el_rprompt.p_pos.v &&
@@ -308,6 +311,36 @@ re_refresh(EditLine *el)
 	    cur.h, cur.v));
 	term_move_to_line(el, cur.v);	/* go to where the cursor is */
 	term_move_to_char(el, cur.h);
+
+	re_highlight(el, &start, &end, &cur);
+}
+
+
+/* re_highlight():
+ *	Give the EL_REFRESHFN hook a chance to redraw the input buffer,
+ *	which spans start to end on the screen, with decorations. The
+ *	decorated text occupies the same cells, so afterwards the cursor
+ *	is at end and we move it back to cur.
+ */
+private void
+re_highlight(EditLine *el, coord_t *start, coord_t *end, coord_t *cur)
+{
+	const char *s;
+
+	if (el->el_refresh.r_hook == NULL ||
+	    el->el_line.buffer == el->el_line.lastchar)
+		return;
+	if ((s = (*el->el_refresh.r_hook)(el)) == NULL)
+		return;
+
+	term_move_to_line(el, start->v);
+	term_move_to_char(el, start->h);
+	(void) fputs(s, el->el_outfile);
+	el->el_cursor.h = end->h;
+	el->el_cursor.v = end->v;
+	term_move_to_line(el, cur->v);
+	term_move_to_char(el, cur->h);
+	term__flush();
 }
 
 
@@ -1038,6 +1071,10 @@ re_fastaddc(EditLine *el)
 
 	c = el->el_line.cursor[-1];
 
+	if (el->el_refresh.r_hook != NULL) {
+		re_refresh(el);	/* the hook redraws the whole line */
+		return;
+	}
 	if (c == '\t' || el->el_line.cursor != el->el_line.lastchar) {
 		re_refresh(el);	/* too hard to handle */
 		return;
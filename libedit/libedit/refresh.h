@@ -50,6 +50,7 @@ typedef struct {
 	coord_t	r_cursor;	/* Refresh cursor position	*/
 	int	r_oldcv;	/* Vertical locations		*/
 	int	r_newcv;
+	el_rfunc_t r_hook;	/* EL_REFRESHFN, or NULL	*/
 } el_refresh_t;
 
 protected void	re_putc(EditLine *, int, int);
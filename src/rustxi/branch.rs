@@ -0,0 +1,39 @@
+/**
+ *  branch: named parallel sessions.
+ *
+ *  A branch is a CUR process plus everything VISOR knows about it:
+ *  its history and its snapshots. Only the active branch's CUR has a
+ *  TRY reading code; every other branch's CUR is stopped until
+ *  .switch wakes it.
+ **/
+
+use std::libc::types::os::arch::posix88::{pid_t};
+
+use capture;
+use msg;
+use snapshot;
+
+pub struct Branch {
+    name: ~str,
+    /// the branch's CUR, stopped while the branch is not active.
+    cur: pid_t,
+    /// the branch's own history of commands and their outcomes.
+    cmd: ~[~str],
+    failed: ~[bool],
+    outputs: ~[capture::Output],
+    edits: ~[~[msg::GraphEdit]],
+    snapshots: snapshot::Snapshots,
+    checkpoint: Option<~str>,
+}
+
+impl Branch {
+    /// Number of commands in the branch's history that succeeded.
+    pub fn ok_count(&self) -> uint {
+        self.failed.iter().count(|&f| !f)
+    }
+}
+
+/// Position of the branch with this name.
+pub fn find(branches: &[Branch], name: &str) -> Option<uint> {
+    branches.iter().position(|b| b.name.as_slice() == name)
+}
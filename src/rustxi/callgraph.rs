@@ -1,227 +1,541 @@
+/**
+ *  callgraph: which session fns call which, and which items use which.
+ *
+ *  Besides fns, the nodes are the structs, enums, traits, impls and
+ *  statics the session defines, so that redefining a type reaches the
+ *  impls and fns that use it. Method calls go through a node per method
+ *  name, which uses every impl and trait defining that method; a fn
+ *  calling it uses the method node. Edges still read "uses", callers
+ *  still "calls".
+ *
+ *  Names are interned: each fn gets a small id, its index in fns(),
+ *  and the edges are adjacency lists indexed by id, kept in both
+ *  directions, so neither callers nor callees of a fn take a scan of
+ *  the whole graph to find.
+ *
+ *  Ids are never reused or shifted. A deleted fn stays behind as a
+ *  tombstone: it calls nothing, but its callers still call it, and
+ *  are broken until it is defined again under the same name, which
+ *  brings back the same id.
+ *
+ *  A fn may call one that is not defined yet: the callee gets an id
+ *  and waits, unresolved, until a definition of that name comes, which
+ *  the fns that call it are then affected by. So fns can be defined in
+ *  any order.
+ **/
+
 use extra::sort::Sort;
-use std::hashmap;
+use std::{hashmap, vec};
+
+use calls;
+use msg;
 
-pub static DEP_NOT_IN_GRAPH: &'static str = "Dependency not in graph";
 pub static FN_NOT_IN_GRAPH: &'static str = "Function not in graph";
 
+/// What a node is.
+#[deriving(Eq, Clone)]
+pub enum Kind {
+    Fn,
+    Struct,
+    Enum,
+    Trait,
+    Impl,
+    Static,
+    /// a method name, standing for all that define it.
+    Method,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Fn => "fn",
+            Struct => "struct",
+            Enum => "enum",
+            Trait => "trait",
+            Impl => "impl",
+            Static => "static",
+            Method => "method",
+        }
+    }
+}
+
+/// The kind a Kind::name() names.
+pub fn kind_named(name: &str) -> Option<Kind> {
+    [Fn, Struct, Enum, Trait, Impl, Static, Method].iter()
+        .find(|k| k.name() == name).map_move(|k| *k)
+}
+
+/// The node that stands for calls to method m.
+pub fn method_node(m: &str) -> ~str {
+    format!(".{:s}", m)
+}
+
+/// Where a name stands in the graph.
+#[deriving(Eq, Clone)]
+pub enum Status {
+    /// called, but never defined.
+    Unresolved,
+    Live,
+    Deleted,
+}
+
 pub trait CallGraph {
-    fn update<'l>(&'l mut self, func: ~str, dependencies: &[&str])
+    /// Define name as a kind of item using dependencies, in place of
+    /// what it used before; returns what that affects.
+    fn define<'l>(&'l mut self, name: ~str, kind: Kind, dependencies: &[&str])
         -> Result<~[&'l ~str], &str>;
     fn delete(&mut self, func: &str) -> Result<~[~str], &str>;
+    /// Delete func alone; what called it is left broken. Returns that.
+    fn remove(&mut self, func: &str) -> Result<~[~str], &str>;
+    /// All names by id, deleted and unresolved ones too.
     fn fns<'l>(&'l self) -> &'l ~[~str];
+    fn status(&self, id: uint) -> Status;
+    /// What id was defined as; a name not defined yet counts as a fn.
+    fn kind(&self, id: uint) -> Kind;
+    /// What id uses itself.
+    fn fns_directly_used_by(&self, id: uint) -> ~[uint];
+    /// The id of a fn in the graph, not deleted.
+    fn id_of(&self, func: &str) -> Option<uint>;
     fn fns_directly_affected_by(&self, id: uint) -> ~[uint];
 
+    fn update<'l>(&'l mut self, func: ~str, dependencies: &[&str])
+        -> Result<~[&'l ~str], &str> {
+        self.define(func, Fn, dependencies)
+    }
+
     fn add(&mut self, func: ~str, dependencies: &[&str]) -> Result<(), &str> {
         assert!(!self.contains(&[func.as_slice()]));
+        // a fn that was deleted comes back with its old callers, so
+        // unlike a new one it may affect others.
         match self.update(func, dependencies) {
-            Ok(affected) => {
-                assert!(affected.len() == 0);
-                Ok(())
-            },
+            Ok(_) => Ok(()),
             Err(e) => Err(e),
         }
     }
 
+    fn is_live(&self, id: uint) -> bool {
+        self.status(id) == Live
+    }
+
+    /// The names called but not defined yet, in id order.
+    fn unresolved(&self) -> ~[uint] {
+        range(0, self.fns().len()).filter(|&id| {
+            self.status(id) == Unresolved && self.fns_directly_affected_by(id).len() > 0
+        }).collect()
+    }
+
+    /// The fns that call a deleted fn, in id order: they need
+    /// redefining, or it does.
+    fn broken(&self) -> ~[uint] {
+        let n = self.fns().len();
+        let mut b = vec::from_elem(n, false);
+        for id in range(0, n) {
+            if self.status(id) == Deleted {
+                for &c in self.fns_directly_affected_by(id).iter() {
+                    b[c] = true;
+                }
+            }
+        }
+        range(0, n).filter(|&i| b[i]).collect()
+    }
+
+    // breadth first through the callers; id itself only if it is in
+    // a cycle. In id order.
     fn fns_affected_by(&self, id: uint) -> ~[uint] {
-        let mut affected_ids = self.fns_directly_affected_by(id);
-        let mut ids = ~[];
-        while ids != affected_ids {
-            ids = affected_ids;
-            affected_ids = ids + do ids.flat_map |&i| {
-                self.fns_directly_affected_by(i)
-            };
-            affected_ids.qsort();
-            affected_ids.dedup();
+        let mut seen = vec::from_elem(self.fns().len(), false);
+        let mut queue = self.fns_directly_affected_by(id);
+        let mut head = 0u;
+        while head < queue.len() {
+            let i = queue[head];
+            head += 1;
+            if !seen[i] {
+                seen[i] = true;
+                queue.push_all(self.fns_directly_affected_by(i));
+            }
         }
-        affected_ids
+        range(0, seen.len()).filter(|&i| seen[i]).collect()
     }
 
     fn contains(&self, fns: &[&str]) -> bool {
-        for &f in fns.iter() {
-            match position(f, *self.fns()) {
-                None => return false,
-                Some(*) => (),
+        fns.iter().all(|f| self.id_of(*f).is_some())
+    }
+}
+
+/// Record the items a compiled snippet defined, each with those of the
+/// names it uses that are session items: already in the graph, or
+/// defined alongside it. New items go in first, so that they are not
+/// taken for unresolved names; rustc resolved every name, and the rest
+/// are library items or locals, which the graph leaves out. Then the
+/// method nodes take in the impls and traits that define them. A type
+/// gets no edge to its impls, which would make every type and impl a
+/// cycle; unit_of follows impls to their type instead, and a redefined
+/// impl affects what uses its type. The changes go on edits, for VISOR;
+/// returns the items affected by redefinitions, less the ones just
+/// defined, which have compiled.
+pub fn record<G: CallGraph>(graph: &mut G, defined: &[calls::Item],
+                            edits: &mut ~[msg::GraphEdit]) -> ~[~str] {
+    let mut affected = ~[];
+    let mut redefined = ~[];
+    for d in defined.iter() {
+        if !graph.contains(&[d.name.as_slice()]) {
+            define(graph, d.name.clone(), d.kind, [], edits, &mut affected);
+        } else {
+            redefined.push(d.name.clone());
+        }
+    }
+    for d in defined.iter() {
+        for m in d.methods.iter() {
+            let node = method_node(*m);
+            let mut deps = match graph.id_of(node.as_slice()) {
+                Some(id) => graph.fns_directly_used_by(id).map(|&i| graph.fns()[i].clone()),
+                None => ~[],
+            };
+            if !deps.contains(&d.name) {
+                deps.push(d.name.clone());
+                let deps: ~[&str] = deps.iter().map(|d| d.as_slice()).collect();
+                define(graph, node, Method, deps, edits, &mut affected);
             }
         }
-        return true;
     }
+    for d in defined.iter() {
+        let deps: ~[&str] = d.uses.iter().filter(|c| graph.contains(&[c.as_slice()]))
+            .map(|c| c.as_slice()).collect();
+        define(graph, d.name.clone(), d.kind, deps, edits, &mut affected);
+    }
+    // a changed impl changes what its type offers, e.g. T::f; a new one
+    // breaks nothing that used the type.
+    for d in defined.iter() {
+        let ty = match d.self_ty {
+            Some(ref t) if redefined.contains(&d.name) => t.as_slice(),
+            _ => continue,
+        };
+        match graph.id_of(ty) {
+            Some(id) => for &i in graph.fns_affected_by(id).iter() {
+                let f = &graph.fns()[i];
+                if !affected.contains(f) {
+                    affected.push(f.clone());
+                }
+            },
+            None => (),
+        }
+    }
+    affected.retain(|a| !defined.iter().any(|d| d.name == *a));
+    affected
 }
 
-fn position(s: &str, l: &[~str]) -> Option<uint> {
-    if l.len() > 0 {
-        let mut i = 0u;
-        for e in l.iter() {
-            if s == *e {
-                return Some(i);
+// define, note the edit, and add what it affects to affected.
+fn define<G: CallGraph>(graph: &mut G, name: ~str, kind: Kind, deps: &[&str],
+                        edits: &mut ~[msg::GraphEdit], affected: &mut ~[~str]) {
+    edits.push(msg::Define(name.clone(), kind, deps.map(|c| c.to_owned())));
+    match graph.define(name, kind, deps) {
+        Ok(fs) => for f in fs.iter() {
+            if !affected.contains(*f) {
+                affected.push((*f).clone());
             }
-            i += 1;
+        },
+        Err(e) => fail2!("call graph: {:s}", e),
+    }
+}
+
+/// Make a change TRY made to its graph in VISOR's copy. They were made
+/// in this order before, so they can't fail now.
+pub fn apply<G: CallGraph>(graph: &mut G, edit: &msg::GraphEdit) {
+    match *edit {
+        msg::Define(ref f, kind, ref deps) => {
+            let deps: ~[&str] = deps.iter().map(|d| d.as_slice()).collect();
+            if graph.define(f.clone(), kind, deps).is_err() {
+                fail2!("call graph: cannot define {:s} as TRY did", *f);
+            }
+        },
+        msg::Delete(ref f) => {
+            if graph.remove(*f).is_err() {
+                fail2!("call graph: cannot delete {:s} as TRY did", *f);
+            }
+        },
+    }
+}
+
+// a name as a double-quoted string, good for DOT and for JSON.
+fn quote(s: &str) -> ~str {
+    let mut q = ~"\"";
+    for c in s.iter() {
+        if c == '"' || c == '\\' {
+            q.push_char('\\');
+        }
+        q.push_char(c);
+    }
+    q.push_char('"');
+    q
+}
+
+// interned fn names: a name's id is its index in names. Once
+// interned, a name keeps its id, whatever its status.
+struct Symbols {
+    ids: hashmap::HashMap<~str, uint>,
+    names: ~[~str],
+    status: ~[Status],
+    kinds: ~[Kind],
+}
+
+impl Symbols {
+    fn new() -> Symbols {
+        Symbols { ids: hashmap::HashMap::new(), names: ~[], status: ~[], kinds: ~[] }
+    }
+
+    fn find_live(&self, name: &str) -> Option<uint> {
+        match self.find(name) {
+            Some(id) if self.status[id] == Live => Some(id),
+            _ => None,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<uint> {
+        match self.ids.find_equiv(&name) {
+            Some(&id) => Some(id),
+            None => None,
         }
     }
-    return None;
+
+    // a name not seen before starts out unresolved.
+    fn intern(&mut self, name: &str) -> uint {
+        match self.find(name) {
+            Some(id) => id,
+            None => {
+                let id = self.names.len();
+                self.ids.insert(name.to_owned(), id);
+                self.names.push(name.to_owned());
+                self.status.push(Unresolved);
+                self.kinds.push(Fn);
+                id
+            },
+        }
+    }
+
+    // define func as using dependencies, interning what is new.
+    fn define(&mut self, func: &str, kind: Kind, dependencies: &[&str]) -> (uint, ~[uint]) {
+        let id = self.intern(func);
+        self.status[id] = Live;
+        self.kinds[id] = kind;
+        let mut deps = ~[];
+        for d in dependencies.iter() {
+            deps.push(self.intern(*d));
+        }
+        (id, deps)
+    }
+}
+
+// edges both ways, indexed by id: out[a] holds b just when into[b]
+// holds a.
+struct Adjacency {
+    out: ~[~[uint]],
+    into: ~[~[uint]],
+}
+
+impl Adjacency {
+    fn new() -> Adjacency {
+        Adjacency { out: ~[], into: ~[] }
+    }
+
+    // room for ids up to n - 1.
+    fn grow(&mut self, n: uint) {
+        while self.out.len() < n {
+            self.out.push(~[]);
+            self.into.push(~[]);
+        }
+    }
+
+    // make a's out edges exactly bs.
+    fn set_out(&mut self, a: uint, bs: &[uint]) {
+        let old = ::std::util::replace(&mut self.out[a], ~[]);
+        for &b in old.iter() {
+            self.into[b].retain(|&x| x != a);
+        }
+        for &b in bs.iter() {
+            if !self.out[a].contains(&b) {
+                self.out[a].push(b);
+                self.into[b].push(a);
+            }
+        }
+    }
+
+    // make b's in edges exactly as.
+    fn set_in(&mut self, b: uint, sources: &[uint]) {
+        let old = ::std::util::replace(&mut self.into[b], ~[]);
+        for &a in old.iter() {
+            self.out[a].retain(|&x| x != b);
+        }
+        for &a in sources.iter() {
+            if !self.into[b].contains(&a) {
+                self.into[b].push(a);
+                self.out[a].push(b);
+            }
+        }
+    }
+}
+
+fn names_of(syms: &Symbols, ids: &[uint]) -> ~[~str] {
+    ids.map(|&i| syms.names[i].clone())
 }
 
+/// The edges kept from each caller to its callees, the way calls are
+/// written; callers are found by the reverse index.
 pub struct CallerToCalleeGraph {
-    fns: ~[~str],
-    graph: hashmap::HashMap<uint, ~[uint]>,
+    syms: Symbols,
+    /// out: callees, into: callers.
+    adj: Adjacency,
 }
 
 impl CallerToCalleeGraph {
     pub fn new() -> CallerToCalleeGraph {
         CallerToCalleeGraph {
-            fns: ~[],
-            graph: hashmap::HashMap::new(),
+            syms: Symbols::new(),
+            adj: Adjacency::new(),
         }
     }
+
+    /// What fn id calls.
+    pub fn callees<'l>(&'l self, id: uint) -> &'l [uint] {
+        self.adj.out[id].as_slice()
+    }
 }
 
 impl CallGraph for CallerToCalleeGraph {
-    fn update<'l>(&'l mut self, func: ~str, dependencies: &[&str])
+    fn define<'l>(&'l mut self, func: ~str, kind: Kind, dependencies: &[&str])
         -> Result<~[&'l ~str], &str> {
-        // check if all dependencies are in graph
-        if !self.contains(dependencies) {
-            return Err(DEP_NOT_IN_GRAPH);
-        }
-        // get func's position in list of maintained fns
-        let new_fn_position = match self.fns.position_elem(&func) {
-            None => {
-                self.fns.push(func);
-                self.fns.len() - 1
-            },
-            Some(p) => p,
-        };
-        // temporarily remove func from graph
-        self.graph.pop(&new_fn_position);
-        // update func's dependencies to point to func
-        for &d in dependencies.iter() {
-            match position(d, self.fns) {
-                None => unreachable!(),
-                Some(pos) => {
-                    do self.graph.insert_or_update_with(new_fn_position, ~[pos])
-                        |_, deps| {
-                            if !deps.contains(&pos) {
-                                deps.push(pos);
-                            }
-                        };
-                },
-            }
-        }
+        let (id, deps) = self.syms.define(func.as_slice(), kind, dependencies);
+        self.adj.grow(self.syms.names.len());
+        // func's callees are replaced, not added to.
+        self.adj.set_out(id, deps);
         // return list of affected fns
-        Ok(self.fns_affected_by(new_fn_position).map(|&i| &self.fns[i]))
+        Ok(self.fns_affected_by(id).map(|&i| &self.syms.names[i]))
     }
 
     fn delete(&mut self, func: &str) -> Result<~[~str], &str> {
-        if !self.contains(&[func]) {
-            return Err(FN_NOT_IN_GRAPH);
-        }
-        let fn_position = position(func, self.fns).unwrap();
-        let affected_ids = self.fns_affected_by(fn_position);
-        // remove func and its deps from graph
-        self.graph.pop(&fn_position);
-        for id in affected_ids.iter() {
-            self.graph.pop(id);
-        }
-        let affected = affected_ids.map(|&i| self.fns[i].clone());
-        do self.fns.retain |s| {
-            if func == *s {
-                false
-            } else {
-                affected.position_elem(s).is_none()
-            }
+        let id = match self.syms.find_live(func) {
+            None => return Err(FN_NOT_IN_GRAPH),
+            Some(id) => id,
+        };
+        // func and all its callers, direct or not, go.
+        let affected_ids = self.fns_affected_by(id);
+        for &i in [id].iter().chain(affected_ids.iter()) {
+            self.syms.status[i] = Deleted;
+            self.adj.set_out(i, []);
         }
-        Ok(affected)
+        Ok(names_of(&self.syms, affected_ids))
+    }
+
+    fn remove(&mut self, func: &str) -> Result<~[~str], &str> {
+        let id = match self.syms.find_live(func) {
+            None => return Err(FN_NOT_IN_GRAPH),
+            Some(id) => id,
+        };
+        self.syms.status[id] = Deleted;
+        self.adj.set_out(id, []);
+        let mut callers = self.adj.into[id].clone();
+        callers.qsort();
+        Ok(names_of(&self.syms, callers))
     }
 
     fn fns_directly_affected_by(&self, id: uint) -> ~[uint] {
-        self.graph.iter().filter(|&(_, v)| v.contains(&id)).map(|(&k, _)| k).collect()
+        self.adj.into[id].clone()
+    }
+
+    fn fns_directly_used_by(&self, id: uint) -> ~[uint] {
+        self.adj.out[id].clone()
+    }
+
+    fn kind(&self, id: uint) -> Kind {
+        self.syms.kinds[id]
+    }
+
+    fn status(&self, id: uint) -> Status {
+        self.syms.status[id]
+    }
+
+    fn id_of(&self, func: &str) -> Option<uint> {
+        self.syms.find_live(func)
     }
 
     fn fns<'l>(&'l self) -> &'l ~[~str] {
-        &self.fns
+        &self.syms.names
     }
 }
 
+/// The same edges kept the other way round, from each callee to its
+/// callers, which is the direction a change spreads in.
 pub struct CalleeToCallerGraph {
-    fns: ~[~str],
-    graph: hashmap::HashMap<uint, ~[uint]>,
+    syms: Symbols,
+    /// out: callers, into: callees.
+    adj: Adjacency,
 }
 
 impl CalleeToCallerGraph {
     pub fn new() -> CalleeToCallerGraph {
         CalleeToCallerGraph {
-            fns: ~[],
-            graph: hashmap::HashMap::new(),
+            syms: Symbols::new(),
+            adj: Adjacency::new(),
         }
     }
 }
 
 impl CallGraph for CalleeToCallerGraph {
-    fn update<'l>(&'l mut self, func: ~str, dependencies: &[&str])
+    fn define<'l>(&'l mut self, func: ~str, kind: Kind, dependencies: &[&str])
         -> Result<~[&'l ~str], &str> {
-        // check if all dependencies are in graph
-        if !self.contains(dependencies) {
-            return Err(DEP_NOT_IN_GRAPH);
-        }
-        // get func's position in list of maintained fns
-        let new_fn_position = match self.fns.position_elem(&func) {
-            None => {
-                self.fns.push(func);
-                self.fns.len() - 1
-            },
-            Some(p) => p,
-        };
-        // get list of affected fns
-        let mut caller_ids = self.fns_affected_by(new_fn_position);
-        // update func's dependencies to point to func
-        for &d in dependencies.iter() {
-            match position(d, self.fns) {
-                None => unreachable!(),
-                Some(pos) => {
-                    do self.graph.insert_or_update_with(pos, ~[new_fn_position])
-                        |_, deps| {
-                            if !deps.contains(&new_fn_position) {
-                                deps.push(new_fn_position);
-                            }
-                        };
-                },
-            }
-        }
-        caller_ids.push_all(self.fns_affected_by(new_fn_position));
-        caller_ids.qsort();
-        caller_ids.dedup();
-        Ok(caller_ids.map(|&i| &self.fns[i]))
+        let (id, deps) = self.syms.define(func.as_slice(), kind, dependencies);
+        self.adj.grow(self.syms.names.len());
+        // func is a caller of each dependency, and of nothing else.
+        self.adj.set_in(id, deps);
+        Ok(self.fns_affected_by(id).map(|&i| &self.syms.names[i]))
     }
 
     fn delete(&mut self, func: &str) -> Result<~[~str], &str> {
-        if !self.contains(&[func]) {
-            return Err(FN_NOT_IN_GRAPH);
-        }
-        let fn_position = position(func, self.fns).unwrap();
-        let affected_ids = self.fns_affected_by(fn_position);
-        // remove func and its deps from graph
-        self.graph.pop(&fn_position);
-        for id in affected_ids.iter() {
-            self.graph.pop(id);
-        }
-        let affected = affected_ids.map(|&i| self.fns[i].clone());
-        do self.fns.retain |s| {
-            if func == *s {
-                false
-            } else {
-                affected.position_elem(s).is_none()
-            }
+        let id = match self.syms.find_live(func) {
+            None => return Err(FN_NOT_IN_GRAPH),
+            Some(id) => id,
+        };
+        let affected_ids = self.fns_affected_by(id);
+        for &i in [id].iter().chain(affected_ids.iter()) {
+            self.syms.status[i] = Deleted;
+            self.adj.set_in(i, []);
         }
-        Ok(affected)
+        Ok(names_of(&self.syms, affected_ids))
+    }
+
+    fn remove(&mut self, func: &str) -> Result<~[~str], &str> {
+        let id = match self.syms.find_live(func) {
+            None => return Err(FN_NOT_IN_GRAPH),
+            Some(id) => id,
+        };
+        // it calls nothing now; what called it still does.
+        self.syms.status[id] = Deleted;
+        self.adj.set_in(id, []);
+        let mut callers = self.adj.out[id].clone();
+        callers.qsort();
+        Ok(names_of(&self.syms, callers))
     }
 
     fn fns_directly_affected_by(&self, id: uint) -> ~[uint] {
-        match self.graph.find(&id) {
-            None => ~[],
-            Some(p) => p.clone(),
-        }
+        self.adj.out[id].clone()
+    }
+
+    fn fns_directly_used_by(&self, id: uint) -> ~[uint] {
+        self.adj.into[id].clone()
+    }
+
+    fn kind(&self, id: uint) -> Kind {
+        self.syms.kinds[id]
+    }
+
+    fn status(&self, id: uint) -> Status {
+        self.syms.status[id]
+    }
+
+    fn id_of(&self, func: &str) -> Option<uint> {
+        self.syms.find_live(func)
     }
 
     fn fns<'l>(&'l self) -> &'l ~[~str] {
-        &self.fns
+        &self.syms.names
     }
 }
 
@@ -237,13 +551,270 @@ impl BothWayGraph {
             callee_caller: CalleeToCallerGraph::new(),
         }
     }
+
+    /// Everything func calls, directly or not.
+    pub fn deps_of(&self, func: &str) -> ~[~str] {
+        match self.id_of(func) {
+            None => ~[],
+            Some(id) => self.names(self.reach(id).move_iter()
+                                   .filter(|&i| i != id && self.is_live(i)).collect()),
+        }
+    }
+
+    /// What checking func on its own takes: everything it calls,
+    /// directly or not, and the impls of each type among those, which
+    /// bring what they call in turn. Only an impl knows its type, as
+    /// one of the names it uses, so this is the one place that follows
+    /// an edge backwards.
+    pub fn unit_of(&self, func: &str) -> ~[~str] {
+        let id = match self.id_of(func) {
+            None => return ~[],
+            Some(id) => id,
+        };
+        let mut seen = vec::from_elem(self.fns().len(), false);
+        let mut todo = ~[id];
+        while todo.len() > 0 {
+            let i = todo.pop();
+            let mut next = self.caller_callee.callees(i).to_owned();
+            if self.kind(i) == Struct || self.kind(i) == Enum {
+                next.push_all_move(self.fns_directly_affected_by(i).move_iter()
+                                   .filter(|&c| self.kind(c) == Impl).collect());
+            }
+            for &c in next.iter() {
+                if !seen[c] {
+                    seen[c] = true;
+                    todo.push(c);
+                }
+            }
+        }
+        self.names(range(0, seen.len()).filter(|&i| seen[i] && i != id && self.is_live(i))
+                   .collect())
+    }
+
+    /// What func calls itself, deleted fns left out.
+    pub fn callees_of(&self, func: &str) -> ~[~str] {
+        match self.id_of(func) {
+            None => ~[],
+            Some(id) => self.names(self.caller_callee.callees(id).iter()
+                                   .map(|&i| i).filter(|&i| self.is_live(i)).collect()),
+        }
+    }
+
+    /// What func is.
+    pub fn kind_of(&self, func: &str) -> Option<Kind> {
+        self.id_of(func).map_move(|id| self.kind(id))
+    }
+
+    /// What func calls itself, each name with its status.
+    pub fn calls_of(&self, func: &str) -> ~[(~str, Status)] {
+        match self.id_of(func) {
+            None => ~[],
+            Some(id) => self.caller_callee.callees(id).iter()
+                .map(|&i| (self.fns()[i].clone(), self.status(i))).collect(),
+        }
+    }
+
+    /// The fns defined and not deleted, in the order they were first
+    /// added.
+    pub fn live(&self) -> ~[~str] {
+        self.names(range(0, self.fns().len()).filter(|&i| self.is_live(i)).collect())
+    }
+
+    /// The fns left calling a deleted fn.
+    pub fn broken_fns(&self) -> ~[~str] {
+        self.names(self.broken())
+    }
+
+    /// The names called but not defined yet.
+    pub fn unresolved_fns(&self) -> ~[~str] {
+        self.names(self.unresolved())
+    }
+
+    /// What calls func itself.
+    pub fn callers_of(&self, func: &str) -> ~[~str] {
+        match self.id_of(func) {
+            None => ~[],
+            Some(id) => self.names(self.fns_directly_affected_by(id)),
+        }
+    }
+
+    /// What calls func, directly or not: what a change to it affects.
+    pub fn dependents_of(&self, func: &str) -> ~[~str] {
+        match self.id_of(func) {
+            None => ~[],
+            Some(id) => self.names(self.fns_affected_by(id)),
+        }
+    }
+
+    /// The groups of fns that call each other round in a circle, a fn
+    /// that calls itself included: the strongly connected components,
+    /// found Tarjan's way.
+    pub fn cycles(&self) -> ~[~[~str]] {
+        let n = self.fns().len();
+        let mut t = Tarjan {
+            index: vec::from_elem(n, None),
+            low: vec::from_elem(n, 0u),
+            on_stack: vec::from_elem(n, false),
+            stack: ~[],
+            next: 0,
+            groups: ~[],
+        };
+        for id in range(0, n) {
+            if t.index[id].is_none() {
+                self.strong_connect(id, &mut t);
+            }
+        }
+        let mut groups = t.groups;
+        for g in groups.mut_iter() {
+            g.qsort();
+        }
+        groups.retain(|g| g.len() > 1 || self.caller_callee.callees(g[0]).contains(&g[0]));
+        // each is sorted, so this puts them in order of their first fn.
+        groups.qsort();
+        groups.move_iter().map(|g| self.names(g)).collect()
+    }
+
+    fn strong_connect(&self, id: uint, t: &mut Tarjan) {
+        t.index[id] = Some(t.next);
+        t.low[id] = t.next;
+        t.next += 1;
+        t.stack.push(id);
+        t.on_stack[id] = true;
+        for &c in self.caller_callee.callees(id).iter() {
+            match t.index[c] {
+                None => {
+                    self.strong_connect(c, t);
+                    t.low[id] = ::std::cmp::min(t.low[id], t.low[c]);
+                },
+                Some(ci) if t.on_stack[c] => t.low[id] = ::std::cmp::min(t.low[id], ci),
+                Some(_) => (),
+            }
+        }
+        if Some(t.low[id]) == t.index[id] {
+            let mut group = ~[];
+            loop {
+                let j = t.stack.pop();
+                t.on_stack[j] = false;
+                group.push(j);
+                if j == id {
+                    break;
+                }
+            }
+            t.groups.push(group);
+        }
+    }
+
+    /// The fns nothing calls; other items may well be used only from
+    /// outside the session's fns.
+    pub fn unused(&self) -> ~[~str] {
+        let ids: ~[uint] = range(0, self.fns().len())
+            .filter(|&id| self.is_live(id) && self.kind(id) == Fn
+                    && self.fns_directly_affected_by(id).len() == 0)
+            .collect();
+        self.names(ids)
+    }
+
+    // the ids that id calls, directly or not; id itself if in a cycle.
+    fn reach(&self, id: uint) -> ~[uint] {
+        let mut seen = vec::from_elem(self.fns().len(), false);
+        let mut todo = ~[id];
+        while todo.len() > 0 {
+            let i = todo.pop();
+            for &c in self.caller_callee.callees(i).iter() {
+                if !seen[c] {
+                    seen[c] = true;
+                    todo.push(c);
+                }
+            }
+        }
+        range(0, seen.len()).filter(|&i| seen[i]).collect()
+    }
+
+    /// The graph in Graphviz's DOT language, an edge from each caller
+    /// to each of its callees. Deleted fns are dashed, unresolved ones
+    /// dotted, broken ones red; types are boxes, traits and impls
+    /// hexagons, method nodes diamonds.
+    pub fn to_dot(&self) -> ~str {
+        let broken = self.broken();
+        let mut s = ~"digraph rustxi {\n";
+        for (id, f) in self.fns().iter().enumerate() {
+            let mut attrs = ~[];
+            match self.kind(id) {
+                Struct | Enum | Static => attrs.push("shape=box"),
+                Trait | Impl => attrs.push("shape=hexagon"),
+                Method => attrs.push("shape=diamond"),
+                Fn => (),
+            }
+            if self.status(id) == Deleted {
+                attrs.push("style=dashed");
+            } else if self.status(id) == Unresolved {
+                attrs.push("style=dotted");
+            } else if broken.contains(&id) {
+                attrs.push("color=red");
+            }
+            let attrs = if attrs.len() == 0 { ~"" } else { format!(" [{:s}]", attrs.connect(",")) };
+            s.push_str(format!("    {:s}{:s};\n", quote(*f), attrs));
+        }
+        for (caller, callee) in self.edges().move_iter() {
+            s.push_str(format!("    {:s} -> {:s};\n", quote(caller), quote(callee)));
+        }
+        s.push_str("}\n");
+        s
+    }
+
+    /// The graph as JSON: the fns, an object per caller and callee,
+    /// the kind of each fn that is not one, and which are deleted,
+    /// which unresolved and which broken.
+    pub fn to_json(&self) -> ~str {
+        let nodes: ~[~str] = self.fns().iter().map(|f| quote(*f)).collect();
+        let kinds: ~[~str] = range(0, self.fns().len()).filter(|&i| self.kind(i) != Fn)
+            .map(|i| format!("{:s}:{:s}", quote(self.fns()[i]), quote(self.kind(i).name()))).collect();
+        let edges: ~[~str] = self.edges().move_iter().map(|(caller, callee)| {
+            format!("\\{\"caller\":{:s},\"callee\":{:s}\\}", quote(caller), quote(callee))
+        }).collect();
+        let deleted: ~[~str] = range(0, self.fns().len()).filter(|&i| self.status(i) == Deleted)
+            .map(|i| quote(self.fns()[i])).collect();
+        let unresolved: ~[~str] = self.unresolved_fns().iter().map(|f| quote(*f)).collect();
+        let broken: ~[~str] = self.broken_fns().iter().map(|f| quote(*f)).collect();
+        format!("\\{\"nodes\":[{:s}],\"edges\":[{:s}],\"kinds\":\\{{:s}\\},\
+                 \"deleted\":[{:s}],\"unresolved\":[{:s}],\"broken\":[{:s}]\\}\n",
+                nodes.connect(","), edges.connect(","), kinds.connect(","), deleted.connect(","),
+                unresolved.connect(","), broken.connect(","))
+    }
+
+    // caller and callee names, in the order the fns were added.
+    fn edges(&self) -> ~[(~str, ~str)] {
+        let fns = self.fns();
+        let mut edges = ~[];
+        for id in range(0, fns.len()) {
+            for &c in self.caller_callee.callees(id).iter() {
+                edges.push((fns[id].clone(), fns[c].clone()));
+            }
+        }
+        edges
+    }
+
+    fn names(&self, ids: ~[uint]) -> ~[~str] {
+        let fns = self.fns();
+        ids.map(|&i| fns[i].clone())
+    }
+}
+
+// Tarjan's bookkeeping, for BothWayGraph::cycles.
+struct Tarjan {
+    index: ~[Option<uint>],
+    low: ~[uint],
+    on_stack: ~[bool],
+    stack: ~[uint],
+    next: uint,
+    groups: ~[~[uint]],
 }
 
 impl CallGraph for BothWayGraph {
-    fn update<'l>(&'l mut self, func: ~str, dependencies: &[&str])
+    fn define<'l>(&'l mut self, func: ~str, kind: Kind, dependencies: &[&str])
         -> Result<~[&'l ~str], &str> {
-        let l1 = self.caller_callee.update(func.clone(), dependencies);
-        let l2 = self.callee_caller.update(func, dependencies);
+        let l1 = self.caller_callee.define(func.clone(), kind, dependencies);
+        let l2 = self.callee_caller.define(func, kind, dependencies);
         assert!(l1 == l2);
         l1
     }
@@ -255,11 +826,543 @@ impl CallGraph for BothWayGraph {
         l1
     }
 
+    fn remove(&mut self, func: &str) -> Result<~[~str], &str> {
+        let l1 = self.caller_callee.remove(func);
+        let l2 = self.callee_caller.remove(func);
+        assert!(l1 == l2);
+        l1
+    }
+
+    fn status(&self, id: uint) -> Status {
+        let s = self.caller_callee.status(id);
+        assert!(s == self.callee_caller.status(id));
+        s
+    }
+
     fn fns_directly_affected_by(&self, id: uint) -> ~[uint] {
         self.caller_callee.fns_directly_affected_by(id)
     }
 
+    fn fns_directly_used_by(&self, id: uint) -> ~[uint] {
+        self.caller_callee.fns_directly_used_by(id)
+    }
+
+    fn kind(&self, id: uint) -> Kind {
+        let k = self.caller_callee.kind(id);
+        assert!(k == self.callee_caller.kind(id));
+        k
+    }
+
+    fn id_of(&self, func: &str) -> Option<uint> {
+        self.caller_callee.id_of(func)
+    }
+
     fn fns<'l>(&'l self) -> &'l ~[~str] {
         self.caller_callee.fns()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{rand, task, vec};
+    use std::rand::Rng;
+
+    use calls;
+    use super::{Adjacency, BothWayGraph, CallGraph, CalleeToCallerGraph, CallerToCalleeGraph,
+                Deleted, Enum, Fn, Impl, Kind, Live, Method, Static, Status, Struct, Trait,
+                Unresolved, FN_NOT_IN_GRAPH, method_node, record};
+
+    // names the random tests draw from: few, so they collide often.
+    // The last METHODS of them are method nodes.
+    static POOL: uint = 6;
+    static METHODS: uint = 2;
+
+    fn name(i: uint) -> ~str {
+        if i >= POOL - METHODS {
+            method_node(format!("m{:u}", i))
+        } else {
+            format!("f{:u}", i)
+        }
+    }
+
+    #[deriving(Clone)]
+    enum Op {
+        /// define, as this kind, using these.
+        Define(uint, Kind, ~[uint]),
+        /// add a name that is not live.
+        Add(uint, ~[uint]),
+        Remove(uint),
+        Delete(uint),
+    }
+
+    static ALREADY_LIVE: &'static str = "already live";
+
+    // the graph the slow, obvious way: each fn's uses, and a scan of
+    // them all for callers.
+    struct Model {
+        names: ~[~str],
+        status: ~[Status],
+        kinds: ~[Kind],
+        uses: ~[~[uint]],
+    }
+
+    impl Model {
+        fn new() -> Model {
+            Model { names: ~[], status: ~[], kinds: ~[], uses: ~[] }
+        }
+
+        fn intern(&mut self, n: &str) -> uint {
+            match self.names.iter().position(|m| m.as_slice() == n) {
+                Some(id) => id,
+                None => {
+                    self.names.push(n.to_owned());
+                    self.status.push(Unresolved);
+                    self.kinds.push(Fn);
+                    self.uses.push(~[]);
+                    self.names.len() - 1
+                },
+            }
+        }
+
+        fn find_live(&self, n: &str) -> Option<uint> {
+            match self.names.iter().position(|m| m.as_slice() == n) {
+                Some(id) if self.status[id] == Live => Some(id),
+                _ => None,
+            }
+        }
+
+        fn callers(&self, id: uint) -> ~[uint] {
+            range(0, self.names.len()).filter(|&c| self.uses[c].contains(&id)).collect()
+        }
+
+        fn affected(&self, id: uint) -> ~[uint] {
+            let mut seen = ~[];
+            let mut todo = self.callers(id);
+            while todo.len() > 0 {
+                let i = todo.pop();
+                if !seen.contains(&i) {
+                    seen.push(i);
+                    todo.push_all(self.callers(i));
+                }
+            }
+            range(0, self.names.len()).filter(|i| seen.contains(i)).collect()
+        }
+
+        fn names_of(&self, ids: &[uint]) -> ~[~str] {
+            ids.map(|&i| self.names[i].clone())
+        }
+
+        fn define(&mut self, f: &str, kind: Kind, deps: &[&str]) -> ~[~str] {
+            let id = self.intern(f);
+            self.status[id] = Live;
+            self.kinds[id] = kind;
+            let mut uses = ~[];
+            for d in deps.iter() {
+                let d = self.intern(*d);
+                if !uses.contains(&d) {
+                    uses.push(d);
+                }
+            }
+            self.uses[id] = uses;
+            let affected = self.affected(id);
+            self.names_of(affected)
+        }
+
+        fn remove(&mut self, f: &str) -> Result<~[~str], ~str> {
+            match self.find_live(f) {
+                None => Err(FN_NOT_IN_GRAPH.to_owned()),
+                Some(id) => {
+                    self.status[id] = Deleted;
+                    self.uses[id] = ~[];
+                    let callers = self.callers(id);
+                    Ok(self.names_of(callers))
+                },
+            }
+        }
+
+        fn delete(&mut self, f: &str) -> Result<~[~str], ~str> {
+            match self.find_live(f) {
+                None => Err(FN_NOT_IN_GRAPH.to_owned()),
+                Some(id) => {
+                    let affected = self.affected(id);
+                    for &i in [id].iter().chain(affected.iter()) {
+                        self.status[i] = Deleted;
+                        self.uses[i] = ~[];
+                    }
+                    Ok(self.names_of(affected))
+                },
+            }
+        }
+
+        fn broken(&self) -> ~[uint] {
+            range(0, self.names.len()).filter(|&c| {
+                self.uses[c].iter().any(|&d| self.status[d] == Deleted)
+            }).collect()
+        }
+
+        fn unresolved(&self) -> ~[uint] {
+            range(0, self.names.len()).filter(|&i| {
+                self.status[i] == Unresolved && self.callers(i).len() > 0
+            }).collect()
+        }
+    }
+
+    fn step<G: CallGraph>(g: &mut G, op: &Op) -> Result<~[~str], ~str> {
+        match *op {
+            Define(f, kind, ref deps) => {
+                let deps: ~[~str] = deps.map(|&d| name(d));
+                let deps: ~[&str] = deps.iter().map(|d| d.as_slice()).collect();
+                // update is define as a fn.
+                let r = if kind == Fn {
+                    g.update(name(f), deps)
+                } else {
+                    g.define(name(f), kind, deps)
+                };
+                match r {
+                    Ok(fs) => Ok(fs.map(|f| (*f).clone())),
+                    Err(e) => Err(e.to_owned()),
+                }
+            },
+            Add(f, ref deps) => {
+                if g.contains(&[name(f).as_slice()]) {
+                    return Err(ALREADY_LIVE.to_owned());
+                }
+                let deps: ~[~str] = deps.map(|&d| name(d));
+                let deps: ~[&str] = deps.iter().map(|d| d.as_slice()).collect();
+                match g.add(name(f), deps) {
+                    Ok(()) => Ok(~[]),
+                    Err(e) => Err(e.to_owned()),
+                }
+            },
+            Remove(f) => match g.remove(name(f)) {
+                Ok(fs) => Ok(fs),
+                Err(e) => Err(e.to_owned()),
+            },
+            Delete(f) => match g.delete(name(f)) {
+                Ok(fs) => Ok(fs),
+                Err(e) => Err(e.to_owned()),
+            },
+        }
+    }
+
+    fn model_step(m: &mut Model, op: &Op) -> Result<~[~str], ~str> {
+        match *op {
+            Define(f, kind, ref deps) => {
+                let deps: ~[~str] = deps.map(|&d| name(d));
+                let deps: ~[&str] = deps.iter().map(|d| d.as_slice()).collect();
+                Ok(m.define(name(f), kind, deps))
+            },
+            Add(f, ref deps) => {
+                if m.find_live(name(f)).is_some() {
+                    return Err(ALREADY_LIVE.to_owned());
+                }
+                let deps: ~[~str] = deps.map(|&d| name(d));
+                let deps: ~[&str] = deps.iter().map(|d| d.as_slice()).collect();
+                m.define(name(f), Fn, deps);
+                Ok(~[])
+            },
+            Remove(f) => m.remove(name(f)),
+            Delete(f) => m.delete(name(f)),
+        }
+    }
+
+    // what a graph says about every name, against the model.
+    fn differs<G: CallGraph>(g: &G, m: &Model) -> Option<~str> {
+        if g.fns() != &m.names {
+            return Some(format!("fns() {:?}, model {:?}", g.fns(), m.names));
+        }
+        for id in range(0, m.names.len()) {
+            if g.status(id) != m.status[id] {
+                return Some(format!("{:s} is {:?}, model {:?}", m.names[id], g.status(id), m.status[id]));
+            }
+            if g.kind(id) != m.kinds[id] {
+                return Some(format!("{:s} is a {:s}, model {:s}",
+                                    m.names[id], g.kind(id).name(), m.kinds[id].name()));
+            }
+            let live = if m.status[id] == Live { Some(id) } else { None };
+            if g.id_of(m.names[id]) != live {
+                return Some(format!("id_of({:s}) is {:?}", m.names[id], g.id_of(m.names[id])));
+            }
+            if g.fns_affected_by(id) != m.affected(id) {
+                return Some(format!("{:s} affects {:?}, model {:?}",
+                                    m.names[id], g.fns_affected_by(id), m.affected(id)));
+            }
+        }
+        for i in range(0, POOL) {
+            if g.contains(&[name(i).as_slice()]) != m.find_live(name(i)).is_some() {
+                return Some(format!("contains({:s}) is wrong", name(i)));
+            }
+        }
+        if g.broken() != m.broken() {
+            return Some(format!("broken {:?}, model {:?}", g.broken(), m.broken()));
+        }
+        if g.unresolved() != m.unresolved() {
+            return Some(format!("unresolved {:?}, model {:?}", g.unresolved(), m.unresolved()));
+        }
+        None
+    }
+
+    // out and into mirror each other, without repeats.
+    fn adjacency_ok(adj: &Adjacency) -> bool {
+        range(0, adj.out.len()).all(|a| {
+            adj.out[a].iter().all(|&b| adj.into[b].iter().count(|&x| x == a) == 1)
+        }) && range(0, adj.into.len()).all(|b| {
+            adj.into[b].iter().all(|&a| adj.out[a].iter().count(|&x| x == b) == 1)
+        })
+    }
+
+    // run ops on the graphs and the model; what first went wrong. A
+    // panic, such as BothWayGraph's halves disagreeing, counts too, so
+    // it shrinks like any other failure.
+    fn run(ops: &[Op]) -> Option<~str> {
+        let ops = ops.to_owned();
+        let r = do task::try {
+            run_unguarded(ops)
+        };
+        match r {
+            Ok(wrong) => wrong,
+            Err(_) => Some(~"panicked"),
+        }
+    }
+
+    fn run_unguarded(ops: &[Op]) -> Option<~str> {
+        let mut g1 = CallerToCalleeGraph::new();
+        let mut g2 = CalleeToCallerGraph::new();
+        let mut g3 = BothWayGraph::new();
+        let mut m = Model::new();
+        for (i, op) in ops.iter().enumerate() {
+            let r1 = step(&mut g1, op);
+            let r2 = step(&mut g2, op);
+            let r3 = step(&mut g3, op);
+            let rm = model_step(&mut m, op);
+            if r1 != rm || r2 != rm || r3 != rm {
+                return Some(format!("op {:u}: caller-callee {:?}, callee-caller {:?}, \
+                                     both ways {:?}, model {:?}", i, r1, r2, r3, rm));
+            }
+            let wrong = differs(&g1, &m).map_move(|e| format!("caller-callee: {:s}", e))
+                .or(differs(&g2, &m).map_move(|e| format!("callee-caller: {:s}", e)))
+                .or(differs(&g3, &m).map_move(|e| format!("both ways: {:s}", e)));
+            match wrong {
+                Some(e) => return Some(format!("after op {:u}: {:s}", i, e)),
+                None => (),
+            }
+            if !adjacency_ok(&g1.adj) || !adjacency_ok(&g2.adj)
+                || !adjacency_ok(&g3.caller_callee.adj) || !adjacency_ok(&g3.callee_caller.adj) {
+                return Some(format!("after op {:u}: edges out of step", i));
+            }
+        }
+        None
+    }
+
+    fn random_deps<R: Rng>(rng: &mut R) -> ~[uint] {
+        let mut deps = ~[];
+        for _ in range(0, rng.gen_integer_range(0u, 4)) {
+            deps.push(rng.gen_integer_range(0u, POOL));
+        }
+        deps
+    }
+
+    fn random_ops<R: Rng>(rng: &mut R, n: uint) -> ~[Op] {
+        let mut ops = vec::with_capacity(n);
+        for _ in range(0, n) {
+            let f = rng.gen_integer_range(0u, POOL);
+            let op = match rng.gen_integer_range(0u, 12) {
+                0 .. 5 => {
+                    // method nodes are only ever methods; the rest take
+                    // any other kind, fns most often.
+                    let kind = if f >= POOL - METHODS {
+                        Method
+                    } else {
+                        match rng.gen_integer_range(0u, 10) {
+                            0 => Struct, 1 => Enum, 2 => Trait, 3 => Impl, 4 => Static,
+                            _ => Fn,
+                        }
+                    };
+                    Define(f, kind, random_deps(rng))
+                },
+                6 .. 7 => Add(f, random_deps(rng)),
+                8 .. 9 => Remove(f),
+                _ => Delete(f),
+            };
+            ops.push(op);
+        }
+        ops
+    }
+
+    // ops one step simpler: one op fewer, or one use fewer.
+    fn simpler(ops: &[Op]) -> ~[~[Op]] {
+        let mut out = ~[];
+        for i in range(0, ops.len()) {
+            let mut fewer = ops.to_owned();
+            fewer.remove(i);
+            out.push(fewer);
+        }
+        for (i, op) in ops.iter().enumerate() {
+            match *op {
+                Define(f, kind, ref deps) => for j in range(0, deps.len()) {
+                    let mut d = deps.clone();
+                    d.remove(j);
+                    let mut fewer = ops.to_owned();
+                    fewer[i] = Define(f, kind, d);
+                    out.push(fewer);
+                },
+                Add(f, ref deps) => for j in range(0, deps.len()) {
+                    let mut d = deps.clone();
+                    d.remove(j);
+                    let mut fewer = ops.to_owned();
+                    fewer[i] = Add(f, d);
+                    out.push(fewer);
+                },
+                _ => (),
+            }
+        }
+        out
+    }
+
+    // the smallest ops, by simpler(), that still go wrong.
+    fn shrink(ops: ~[Op]) -> ~[Op] {
+        let mut ops = ops;
+        loop {
+            match simpler(ops).move_iter().find(|s| run(*s).is_some()) {
+                Some(s) => ops = s,
+                None => return ops,
+            }
+        }
+    }
+
+    #[test]
+    fn random_ops_agree_with_model() {
+        for seed in range(0u, 300) {
+            let mut rng = rand::IsaacRng::new_seeded([seed as u8, (seed >> 8) as u8]);
+            let ops = random_ops(&mut rng, 40);
+            match run(ops) {
+                None => (),
+                Some(_) => {
+                    let small = shrink(ops);
+                    fail2!("seed {:u}: {:s}\nshrunk to {:?}", seed, run(small).unwrap(), small);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn forward_reference_resolves() {
+        let mut g = BothWayGraph::new();
+        g.update(~"f", ["g"]);
+        assert_eq!(g.unresolved_fns(), ~[~"g"]);
+        assert!(!g.contains(["g"]));
+        let affected = g.update(~"g", []).unwrap().map(|f| (*f).clone());
+        assert_eq!(affected, ~[~"f"]);
+        assert_eq!(g.unresolved_fns().len(), 0);
+        assert_eq!(g.dependents_of("g"), ~[~"f"]);
+    }
+
+    #[test]
+    fn remove_keeps_ids_and_leaves_callers_broken() {
+        let mut g = BothWayGraph::new();
+        g.update(~"a", []);
+        g.update(~"b", ["a"]);
+        g.update(~"c", ["b"]);
+        assert_eq!(g.remove("a"), Ok(~[~"b"]));
+        assert_eq!(g.broken_fns(), ~[~"b"]);
+        // c still stands, and b kept its id.
+        assert_eq!(g.live(), ~[~"b", ~"c"]);
+        assert_eq!(g.id_of("b"), Some(1));
+        // a comes back as it was, and b with c must be checked again.
+        let affected = g.update(~"a", []).unwrap().map(|f| (*f).clone());
+        assert_eq!(affected, ~[~"b", ~"c"]);
+        assert_eq!(g.id_of("a"), Some(0));
+        assert_eq!(g.broken_fns().len(), 0);
+    }
+
+    #[test]
+    fn delete_takes_callers_along() {
+        let mut g = BothWayGraph::new();
+        g.update(~"a", []);
+        g.update(~"b", ["a"]);
+        g.update(~"c", ["b"]);
+        g.update(~"d", []);
+        assert_eq!(g.delete("a"), Ok(~[~"b", ~"c"]));
+        assert_eq!(g.live(), ~[~"d"]);
+        assert!(g.delete("a").is_err());
+    }
+
+    #[test]
+    fn cycles_and_unused() {
+        let mut g = BothWayGraph::new();
+        g.update(~"a", ["b"]);
+        g.update(~"b", ["a"]);
+        g.update(~"c", ["c", "a"]);
+        assert_eq!(g.cycles(), ~[~[~"a", ~"b"], ~[~"c"]]);
+        assert_eq!(g.unused(), ~[]);
+        g.update(~"c", ["a"]);
+        assert_eq!(g.cycles(), ~[~[~"a", ~"b"]]);
+        assert_eq!(g.unused(), ~[~"c"]);
+    }
+
+    fn item(name: &str, kind: super::Kind, uses: &[&str], methods: &[&str]) -> calls::Item {
+        calls::Item {
+            name: name.to_owned(),
+            kind: kind,
+            uses: uses.map(|u| u.to_owned()),
+            methods: methods.map(|m| m.to_owned()),
+            self_ty: None,
+            source: ~"",
+        }
+    }
+
+    #[test]
+    fn redefined_types_reach_their_users() {
+        let mut g = BothWayGraph::new();
+        let mut edits = ~[];
+        record(&mut g, [item("Point", Struct, [], []),
+                        item("impl Point", Impl, ["Point"], ["norm"])], &mut edits);
+        record(&mut g, [item("len", Fn, [".norm", "Point"], [])], &mut edits);
+        // the struct changes: its impl and, through the method, len.
+        let affected = record(&mut g, [item("Point", Struct, [], [])], &mut edits);
+        assert_eq!(affected, ~[~"impl Point", ~".norm", ~"len"]);
+
+        // VISOR, replaying the edits, ends up with the same graph.
+        let mut h = BothWayGraph::new();
+        for e in edits.iter() {
+            super::apply(&mut h, e);
+        }
+        assert_eq!(h.fns(), g.fns());
+        assert_eq!(h.deps_of("len"), g.deps_of("len"));
+    }
+
+    fn impl_point() -> calls::Item {
+        calls::Item { self_ty: Some(~"Point"), .. item("impl Point", Impl, ["Point"], ["origin"]) }
+    }
+
+    #[test]
+    fn impls_come_with_their_type() {
+        let mut g = BothWayGraph::new();
+        let mut edits = ~[];
+        record(&mut g, [item("Point", Struct, [], []), impl_point()], &mut edits);
+        // start calls Point::origin, which counts as using Point.
+        record(&mut g, [item("start", Fn, ["Point"], [])], &mut edits);
+        assert!(g.unit_of("start").contains(&~"impl Point"));
+        // the type doesn't use its impl: no cycle, and a change to the
+        // type doesn't come back round to it.
+        assert!(g.cycles().is_empty());
+        assert!(!g.dependents_of("Point").contains(&~"Point"));
+
+        // the impl changes: what uses the type is checked again, the impl
+        // itself just compiled.
+        let affected = record(&mut g, [impl_point()], &mut edits);
+        assert!(affected.contains(&~"start"));
+        assert!(!affected.contains(&~"impl Point"));
+
+        // the type changes: it keeps its impl.
+        record(&mut g, [item("Point", Struct, [], [])], &mut edits);
+        assert!(g.unit_of("start").contains(&~"impl Point"));
+        assert!(g.cycles().is_empty());
+
+        let mut h = BothWayGraph::new();
+        for e in edits.iter() {
+            super::apply(&mut h, e);
+        }
+        assert_eq!(h.unit_of("start"), g.unit_of("start"));
+    }
+}
@@ -0,0 +1,172 @@
+/**
+ *  calls: which items the items in a snippet use.
+ *
+ *  After expansion the snippet's crate is walked for items, at any
+ *  depth, since compile puts every snippet inside its my_fn wrapper:
+ *  fns, structs, enums, traits, impls and statics. An item uses the
+ *  plain names, f or T, that it calls, mentions as a path or writes as
+ *  a type, and the T of a path T::f, which is how an associated fn of
+ *  an impl is reached: without resolution a longer path through a
+ *  module can't be told apart from library code, and session items are
+ *  used by plain name anyway. An impl also records the plain name of
+ *  its type, which is how callgraph finds a type's impls. What is used
+ *  inside a nested item belongs to it, not to the item around it.
+ *
+ *  A method call can't be resolved without types either; it uses the
+ *  method's name, which callgraph ties to every impl and trait that
+ *  defines a method of that name.
+ **/
+
+use syntax::ast;
+use syntax::codemap;
+use syntax::parse::token;
+use syntax::visit;
+use syntax::visit::Visitor;
+
+use callgraph;
+
+// the wrapper compile::compile_and_run puts around each snippet.
+static WRAPPER: &'static str = "my_fn";
+
+/// An item a snippet defines, and the plain names it uses.
+pub struct Item {
+    name: ~str,
+    kind: callgraph::Kind,
+    uses: ~[~str],
+    /// for an impl or a trait, the methods it defines.
+    methods: ~[~str],
+    /// for an impl, the type it is for, if that is a plain name.
+    self_ty: Option<~str>,
+    /// its definition, as written, for checking it again later.
+    source: ~str,
+}
+
+struct Collector {
+    codemap: @codemap::CodeMap,
+    found: ~[Item],
+    /// indexes into found of the items we are inside, innermost last.
+    open: ~[uint],
+}
+
+impl Collector {
+    // name is used by the innermost item we are in.
+    fn uses(&mut self, name: ~str) {
+        match self.open.last_opt() {
+            Some(&i) => {
+                let it = &mut self.found[i];
+                // recursion is no dependency.
+                if name != it.name && !it.uses.contains(&name) {
+                    it.uses.push(name);
+                }
+            },
+            None => (),
+        }
+    }
+
+    // the source text under span, on one line.
+    fn text(&self, span: codemap::Span) -> ~str {
+        let words: ~[~str] = self.codemap.span_to_snippet(span).word_iter()
+            .map(|w| w.to_owned()).collect();
+        words.connect(" ")
+    }
+}
+
+// the name a path is, if it is a plain one.
+fn plain(p: &ast::Path) -> Option<~str> {
+    if !p.global && p.segments.len() == 1 {
+        Some(token::ident_to_str(&p.segments[0].identifier).to_owned())
+    } else {
+        None
+    }
+}
+
+// the T of T::f: a type, if it is one of ours, or else a module.
+fn head(p: &ast::Path) -> Option<~str> {
+    if !p.global && p.segments.len() == 2 {
+        Some(token::ident_to_str(&p.segments[0].identifier).to_owned())
+    } else {
+        None
+    }
+}
+
+impl Visitor<()> for Collector {
+    fn visit_item(&mut self, it: @ast::item, e: ()) {
+        let ident = token::ident_to_str(&it.ident).to_owned();
+        let (name, kind, methods, self_ty) = match it.node {
+            ast::item_fn(*) if ident.as_slice() == WRAPPER => return visit::walk_item(self, it, e),
+            ast::item_fn(*) => (ident, callgraph::Fn, ~[], None),
+            ast::item_struct(*) => (ident, callgraph::Struct, ~[], None),
+            ast::item_enum(*) => (ident, callgraph::Enum, ~[], None),
+            ast::item_static(*) => (ident, callgraph::Static, ~[], None),
+            ast::item_trait(_, _, ref ms) => {
+                let methods = ms.map(|m| match *m {
+                    ast::required(ref tm) => token::ident_to_str(&tm.ident).to_owned(),
+                    ast::provided(m) => token::ident_to_str(&m.ident).to_owned(),
+                });
+                (ident, callgraph::Trait, methods, None)
+            },
+            ast::item_impl(_, ref tr, ref ty, ref ms) => {
+                // impls have no name of their own; what they are for
+                // names them.
+                let name = match *tr {
+                    Some(ref t) => format!("impl {:s} for {:s}", self.text(t.path.span), self.text(ty.span)),
+                    None => format!("impl {:s}", self.text(ty.span)),
+                };
+                let methods = ms.map(|m| token::ident_to_str(&m.ident).to_owned());
+                let self_ty = match ty.node {
+                    ast::ty_path(ref p, _, _) => plain(p),
+                    _ => None,
+                };
+                (name, callgraph::Impl, methods, self_ty)
+            },
+            _ => return visit::walk_item(self, it, e),
+        };
+        let source = self.codemap.span_to_snippet(it.span);
+        self.found.push(Item { name: name, kind: kind, uses: ~[], methods: methods,
+                               self_ty: self_ty, source: source });
+        self.open.push(self.found.len() - 1);
+        match it.node {
+            // an impl of a trait uses the trait; the type is walked.
+            ast::item_impl(_, Some(ref t), _, _) => match plain(&t.path) {
+                Some(n) => self.uses(n),
+                None => (),
+            },
+            _ => (),
+        }
+        visit::walk_item(self, it, e);
+        self.open.pop();
+    }
+
+    fn visit_expr(&mut self, ex: @ast::Expr, e: ()) {
+        match ex.node {
+            ast::ExprPath(ref p) | ast::ExprStruct(ref p, _, _) => match plain(p).or(head(p)) {
+                Some(n) => self.uses(n),
+                None => (),
+            },
+            ast::ExprMethodCall(_, _, ident, _, _, _) => {
+                self.uses(callgraph::method_node(token::ident_to_str(&ident)));
+            },
+            _ => (),
+        }
+        visit::walk_expr(self, ex, e);
+    }
+
+    fn visit_ty(&mut self, t: &ast::Ty, e: ()) {
+        match t.node {
+            ast::ty_path(ref p, _, _) => match plain(p) {
+                Some(n) => self.uses(n),
+                None => (),
+            },
+            _ => (),
+        }
+        visit::walk_ty(self, t, e);
+    }
+}
+
+/// The items defined in crate, in the order they appear, with what
+/// they use.
+pub fn collect(crate: &ast::Crate, codemap: @codemap::CodeMap) -> ~[Item] {
+    let mut c = Collector { codemap: codemap, found: ~[], open: ~[] };
+    visit::walk_crate(&mut c, crate, ());
+    c.found
+}
@@ -0,0 +1,83 @@
+/**
+ *  capture: what each snippet printed.
+ *
+ *  TRY's stdout and stderr are pipes to VISOR while it runs code.
+ *  VISOR passes everything on to its own stdout and stderr as it
+ *  comes, and keeps a copy with the snippet's history entry, for .h -v
+ *  and .show. Before TRY becomes CUR the real stdout and stderr are
+ *  put back, so nothing CUR prints gets attributed to a snippet.
+ **/
+
+use std::{io, libc, vec};
+use std::libc::{c_int, c_void};
+
+use util;
+
+/// The real stdout and stderr, while they point at the pipes.
+pub struct Saved {
+    stdout: c_int,
+    stderr: c_int,
+}
+
+/// Point stdout at out_fd and stderr at err_fd.
+#[fixed_stack_segment]
+pub fn redirect(out_fd: c_int, err_fd: c_int) -> Saved {
+    unsafe {
+        let saved = Saved { stdout: libc::dup(1), stderr: libc::dup(2) };
+        libc::dup2(out_fd, 1);
+        libc::dup2(err_fd, 2);
+        saved
+    }
+}
+
+/// Put stdout and stderr back, once TRY is about to become CUR.
+#[fixed_stack_segment]
+pub fn restore(saved: Saved) {
+    unsafe {
+        // whatever libc still buffers was printed into the pipes.
+        libc::fflush(0 as *libc::FILE);
+        libc::dup2(saved.stdout, 1);
+        libc::dup2(saved.stderr, 2);
+        libc::close(saved.stdout);
+        libc::close(saved.stderr);
+    }
+}
+
+/// Read whatever is waiting on fd, without blocking.
+pub fn drain(fd: c_int) -> ~[u8] {
+    let mut out = ~[];
+    while util::poll_readable(fd, 0) {
+        let mut buf = vec::from_elem(4096, 0u8);
+        let n = do buf.as_mut_buf |p, len| {
+            util::read(fd, p as *mut c_void, len as libc::size_t)
+        };
+        if n <= 0 {
+            break;
+        }
+        out.push_all(buf.slice_to(n as uint));
+    }
+    out
+}
+
+/// One snippet's output, stream by stream.
+#[deriving(Clone)]
+pub struct Output {
+    out: ~[u8],
+    err: ~[u8],
+}
+
+impl Output {
+    pub fn new() -> Output {
+        Output { out: ~[], err: ~[] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.out.len() == 0 && self.err.len() == 0
+    }
+
+    /// Print it again, each part to the stream it first went to.
+    pub fn replay(&self) {
+        io::stdout().write(self.out);
+        io::stderr().write(self.err);
+    }
+}
@@ -3,18 +3,15 @@ use syntax::diagnostic;
 use rustc::driver::{driver, session};
 use rustc::back::link::jit;
 
+use calls;
+
 /**
  *  here is where the heart of the jit-repl will be: here
- *   we actually compile and run the code.
+ *   we actually compile and run the code. Returns the items the
+ *   code defines, with what they use.
  **/
-pub fn compile_and_run(code: &str) {
-    let options = @session::options {
-        crate_type: session::lib_crate,
-        binary: super::PROGRAM_NAME.to_managed(),
-        addl_lib_search_paths: @mut ~[path::Path("/home/minh/opt/lib")],
-        jit: true,
-        .. (*session::basic_options()).clone()
-    };
+pub fn compile_and_run(code: &str) -> ~[calls::Item] {
+    let options = options(true);
     // the link directive is to silence rustc's warning
     // no_mangle is to preserve the name so that rustc::back::link::exec can
     // extract it.
@@ -33,10 +30,44 @@ fn my_fn() -> int \{
 
     let crate = driver::phase_1_parse_input(sess, cfg.clone(), &input);
     let expanded_crate = driver::phase_2_configure_and_expand(sess, cfg, crate);
+    let defined = calls::collect(&expanded_crate, sess.codemap);
     let analysis = driver::phase_3_run_analysis_passes(sess, &expanded_crate);
     println!("[compile_and_run] cannot get here");
     let trans = driver::phase_4_translate_to_llvm(sess, expanded_crate, &analysis, outputs);
     driver::phase_5_run_llvm_passes(sess, &trans, outputs);
 
     jit::consume_engine();
+    defined
+}
+
+/**
+ *  type-check fns again, without generating or running anything: the
+ *  callers of a fn that was just redefined, with all they call. Like
+ *  compile_and_run this fails if the code no longer compiles. The
+ *  code that runs is still what was jitted when each was defined.
+ **/
+pub fn check(code: &str) {
+    let options = options(false);
+    let input = driver::str_input(format!(r###"
+\#[link(name="rustxi_check",
+        vers="0.0")];
+
+{:s}"###, code).to_managed());
+    let sess = driver::build_session(options, @diagnostic::DefaultEmitter as
+                                        @diagnostic::Emitter);
+    let cfg = driver::build_configuration(sess);
+
+    let crate = driver::phase_1_parse_input(sess, cfg.clone(), &input);
+    let expanded_crate = driver::phase_2_configure_and_expand(sess, cfg, crate);
+    driver::phase_3_run_analysis_passes(sess, &expanded_crate);
+}
+
+fn options(jit: bool) -> @session::options {
+    @session::options {
+        crate_type: session::lib_crate,
+        binary: super::PROGRAM_NAME.to_managed(),
+        addl_lib_search_paths: @mut ~[path::Path("/home/minh/opt/lib")],
+        jit: jit,
+        .. (*session::basic_options()).clone()
+    }
 }
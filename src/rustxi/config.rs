@@ -0,0 +1,249 @@
+/**
+ *  config: user settings from the rc file.
+ *
+ *  The rc file is $RUSTXIRC if set, else ~/.rustxirc. Each line is
+ *  `key = value`; blank lines and lines starting with '#' are ignored.
+ *  A missing rc file just means the defaults.
+ **/
+
+use std::{io, os, path};
+use std::num::CheckedMul;
+
+use sandbox;
+
+pub struct Config {
+    /// colorize the input line as it is typed (only on a terminal).
+    highlight: bool,
+
+    /// how long VISOR waits at shutdown for CUR and TRY to quit on
+    /// their own before killing them, in milliseconds.
+    quit_deadline_ms: uint,
+
+    /// how many earlier states to keep, as stopped processes, for .undo
+    /// and .goto; 0 keeps only those named with .checkpoint.
+    snapshots: uint,
+
+    /// soft rlimits for TRY; 0 means leave the inherited limit alone.
+    /// address space and file size in bytes (K, M and G suffixes
+    /// allowed), cpu time in seconds.
+    limit_as: u64,
+    limit_cpu: u64,
+    limit_nofile: u64,
+    limit_fsize: u64,
+
+    /// a cgroup v2 directory we may create children in, e.g.
+    /// /sys/fs/cgroup/user.slice/user-1000.slice/user@1000.service.
+    /// CUR and TRY then run in a cgroup of their own under it.
+    cgroup: Option<~str>,
+    /// limits for that cgroup; 0 means no limit.
+    cgroup_memory_max: u64,
+    cgroup_cpu_percent: uint,
+    cgroup_pids_max: uint,
+
+    /// run TRY in namespaces under a seccomp filter; also --sandbox.
+    sandbox: bool,
+    /// the syscalls the sandbox allows. In the rc file, names add to
+    /// the default list and -names remove from it.
+    sandbox_allow: ~[~str],
+
+    /// trace TRY's syscalls, to tell the user about the effects of a
+    /// failed snippet that rollback can't undo: file writes and the like.
+    /// Off in the sandbox, which doesn't allow ptrace.
+    trace_effects: bool,
+
+    /// when TRY crashes, attach this gdb to it for a backtrace (.bt).
+    backtrace: bool,
+    gdb: ~str,
+
+    /// pass TRY's output through VISOR, keeping a copy with each
+    /// history entry for .h -v and .show. Off, snippets print straight
+    /// to the terminal, and can tell that they do.
+    capture: bool,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            highlight: true,
+            quit_deadline_ms: 2000,
+            snapshots: 0,
+            limit_as: 0,
+            limit_cpu: 0,
+            limit_nofile: 0,
+            limit_fsize: 0,
+            cgroup: None,
+            cgroup_memory_max: 0,
+            cgroup_cpu_percent: 0,
+            cgroup_pids_max: 0,
+            sandbox: false,
+            sandbox_allow: sandbox::DEFAULT_ALLOW.iter().map(|s| s.to_owned()).collect(),
+            trace_effects: true,
+            backtrace: true,
+            gdb: ~"gdb",
+            capture: true,
+        }
+    }
+
+    /// The defaults, overridden by whatever the rc file sets. Bad
+    /// lines are reported and skipped rather than being fatal.
+    pub fn load() -> Config {
+        let mut config = Config::new();
+        let rcpath = match rc_path() {
+            None => return config,
+            Some(p) => p,
+        };
+        if !os::path_exists(&rcpath) {
+            return config;
+        }
+        let text = match io::read_whole_file_str(&rcpath) {
+            Err(e) => {
+                println!("{:s}: cannot read {:s}: {:s}",
+                         super::PROGRAM_NAME, rcpath.to_str(), e);
+                return config;
+            },
+            Ok(t) => t,
+        };
+        let mut lineno = 0;
+        for line in text.line_iter() {
+            lineno += 1;
+            let line = line.trim();
+            if line.len() == 0 || line.starts_with("#") {
+                continue;
+            }
+            let r = match line.find('=') {
+                None => Err(~"expected `key = value`"),
+                Some(pos) => config.set(line.slice_to(pos).trim(),
+                                        line.slice_from(pos + 1).trim()),
+            };
+            match r {
+                Err(e) => println!("{:s}:{:d}: {:s}", rcpath.to_str(), lineno, e),
+                Ok(()) => (),
+            }
+        }
+        config
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ~str> {
+        match key {
+            "highlight" => match parse_bool(key, value) {
+                Ok(b) => self.highlight = b,
+                Err(e) => return Err(e),
+            },
+            "quit_deadline_ms" => match parse_uint(key, value) {
+                Ok(n) => self.quit_deadline_ms = n,
+                Err(e) => return Err(e),
+            },
+            "snapshots" => match parse_uint(key, value) {
+                Ok(n) => self.snapshots = n,
+                Err(e) => return Err(e),
+            },
+            "limit_as" => match parse_size(key, value) {
+                Ok(n) => self.limit_as = n,
+                Err(e) => return Err(e),
+            },
+            "limit_cpu" => match parse_uint(key, value) {
+                Ok(n) => self.limit_cpu = n as u64,
+                Err(e) => return Err(e),
+            },
+            "limit_nofile" => match parse_uint(key, value) {
+                Ok(n) => self.limit_nofile = n as u64,
+                Err(e) => return Err(e),
+            },
+            "limit_fsize" => match parse_size(key, value) {
+                Ok(n) => self.limit_fsize = n,
+                Err(e) => return Err(e),
+            },
+            "cgroup" => self.cgroup = if value.len() == 0 { None } else { Some(value.to_owned()) },
+            "cgroup_memory_max" => match parse_size(key, value) {
+                Ok(n) => self.cgroup_memory_max = n,
+                Err(e) => return Err(e),
+            },
+            "cgroup_cpu_percent" => match parse_uint(key, value) {
+                Ok(n) => self.cgroup_cpu_percent = n,
+                Err(e) => return Err(e),
+            },
+            "cgroup_pids_max" => match parse_uint(key, value) {
+                Ok(n) => self.cgroup_pids_max = n,
+                Err(e) => return Err(e),
+            },
+            "sandbox" => match parse_bool(key, value) {
+                Ok(b) => self.sandbox = b,
+                Err(e) => return Err(e),
+            },
+            "trace_effects" => match parse_bool(key, value) {
+                Ok(b) => self.trace_effects = b,
+                Err(e) => return Err(e),
+            },
+            "backtrace" => match parse_bool(key, value) {
+                Ok(b) => self.backtrace = b,
+                Err(e) => return Err(e),
+            },
+            "gdb" => self.gdb = value.to_owned(),
+            "capture" => match parse_bool(key, value) {
+                Ok(b) => self.capture = b,
+                Err(e) => return Err(e),
+            },
+            "sandbox_allow" => for word in value.word_iter() {
+                let (remove, name) = if word.starts_with("-") {
+                    (true, word.slice_from(1))
+                } else {
+                    (false, word)
+                };
+                if sandbox::syscall_number(name).is_none() {
+                    return Err(format!("{:s}: unknown syscall '{:s}'", key, name));
+                }
+                self.sandbox_allow.retain(|s| s.as_slice() != name);
+                if !remove {
+                    self.sandbox_allow.push(name.to_owned());
+                }
+            },
+            _ => return Err(format!("unknown setting '{:s}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn rc_path() -> Option<path::Path> {
+    match os::getenv("RUSTXIRC") {
+        Some(p) => Some(path::Path(p)),
+        None => match os::homedir() {
+            Some(home) => Some(home.push(".rustxirc")),
+            None => None,
+        },
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, ~str> {
+    match value {
+        "true" | "on" | "yes" | "1" => Ok(true),
+        "false" | "off" | "no" | "0" => Ok(false),
+        _ => Err(format!("{:s}: expected on or off, not '{:s}'", key, value)),
+    }
+}
+
+fn parse_uint(key: &str, value: &str) -> Result<uint, ~str> {
+    match from_str::<uint>(value) {
+        Some(n) => Ok(n),
+        None => Err(format!("{:s}: expected a number, not '{:s}'", key, value)),
+    }
+}
+
+// a byte count, optionally with a K, M or G suffix (powers of 1024).
+fn parse_size(key: &str, value: &str) -> Result<u64, ~str> {
+    if value.len() == 0 {
+        return Err(format!("{:s}: expected a size such as 512M", key));
+    }
+    let (digits, shift) = match value.char_at_reverse(value.len()) {
+        'k' | 'K' => (value.slice_to(value.len() - 1), 10),
+        'm' | 'M' => (value.slice_to(value.len() - 1), 20),
+        'g' | 'G' => (value.slice_to(value.len() - 1), 30),
+        _ => (value, 0),
+    };
+    match from_str::<u64>(digits) {
+        Some(n) => match n.checked_mul(&(1u64 << shift)) {
+            Some(size) => Ok(size),
+            None => Err(format!("{:s}: '{:s}' is too large", key, value)),
+        },
+        None => Err(format!("{:s}: expected a size such as 512M, not '{:s}'", key, value)),
+    }
+}
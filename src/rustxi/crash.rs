@@ -0,0 +1,209 @@
+/**
+ *  crash: a symbolized backtrace when TRY dies from a signal.
+ *
+ *  TRY catches the crash signals. The handler forks gdb in batch mode
+ *  to attach to TRY, still alive inside the handler, and print the
+ *  stack of every thread into a temp file that CUR shares; then TRY
+ *  dies of the original signal as it would have. CUR reads the file
+ *  and sends the trace to VISOR ahead of its failed reply.
+ *
+ *  Everything the handler needs is set up beforehand, since there is
+ *  little it may safely do: no allocation, no locks. That includes a
+ *  stack of its own: after a stack overflow there is no room left on
+ *  TRY's to run it. The alternate stack is per thread, so it is only
+ *  there for the thread that calls install().
+ **/
+
+use std::{cast, libc, ptr, vec};
+use std::libc::{c_char, c_int, c_void};
+
+use signum;
+use util;
+
+pub mod ll {
+    use std::libc::{c_char, c_int, c_long, c_void, size_t};
+    use std::libc::types::common::c95::{FILE};
+
+    // x86_64 stack_t
+    pub struct stack_t {
+        ss_sp: *c_void,
+        ss_flags: c_int,
+        ss_size: size_t,
+    }
+
+    pub static SA_ONSTACK: c_int = 0x08000000;
+    pub static SA_RESETHAND: c_int = 0x80000000u32 as c_int;
+
+    // yama: who else may ptrace us
+    pub static PR_SET_PTRACER: c_int = 0x59616d61;
+
+    pub static SEEK_SET: c_int = 0;
+
+    extern {
+        pub fn tmpfile() -> *FILE;
+        pub fn fileno(f: *FILE) -> c_int;
+        pub fn ftruncate(fd: c_int, len: c_long) -> c_int;
+        pub fn lseek(fd: c_int, off: c_long, whence: c_int) -> c_long;
+        pub fn execvp(file: *c_char, argv: **c_char) -> c_int;
+        pub fn sigaltstack(ss: *stack_t, old: *mut stack_t) -> c_int;
+        pub fn _exit(status: c_int) -> !;
+    }
+}
+
+/// The signals that mean TRY crashed, rather than failed or was killed.
+pub static CRASH_SIGNALS: &'static [c_int] = &[
+    signum::SIGSEGV, signum::SIGBUS, signum::SIGILL, signum::SIGFPE, signum::SIGABRT,
+];
+
+static BATCH: &'static [u8] = bytes!("-batch", 0);
+static NX: &'static [u8] = bytes!("-nx", 0);
+static PID_FLAG: &'static [u8] = bytes!("-p", 0);
+static EX: &'static [u8] = bytes!("-ex", 0);
+static BT_ALL: &'static [u8] = bytes!("thread apply all bt", 0);
+
+// set up by install(), for the handler.
+static mut BT_FD: c_int = -1;
+static mut GDB: *c_char = 0 as *c_char;
+static mut PID_BUF: [u8, ..24] = [0, ..24];
+// the handler's stack: enough for it and the fork it makes.
+static mut ALT_STACK: [u8, ..65536] = [0, ..65536];
+
+/// The file the backtrace goes to, shared by CUR and all its TRYs.
+#[fixed_stack_segment]
+pub fn trace_file() -> c_int {
+    unsafe {
+        let f = ll::tmpfile();
+        if f.is_null() { -1 } else { ll::fileno(f) }
+    }
+}
+
+/// In TRY: run gdb on ourselves, via bt_fd, if we crash.
+#[fixed_stack_segment]
+pub fn install(gdb: &str, bt_fd: c_int) {
+    if bt_fd < 0 {
+        return;
+    }
+    unsafe {
+        BT_FD = bt_fd;
+        if GDB.is_null() {
+            // lives as long as we do.
+            GDB = gdb.to_c_str().unwrap();
+        }
+        let stack = ll::stack_t {
+            ss_sp: &ALT_STACK[0] as *u8 as *c_void,
+            ss_flags: 0,
+            ss_size: ALT_STACK.len() as libc::size_t,
+        };
+        ll::sigaltstack(&stack, ptr::mut_null());
+        // reset on entry, so the kill at the end of the handler is fatal.
+        let act = util::ll::sigaction {
+            sa_sigaction: cast::transmute(crash_handler),
+            sa_mask: [0, ..16],
+            sa_flags: ll::SA_ONSTACK | ll::SA_RESETHAND,
+            sa_restorer: ptr::null(),
+        };
+        for &sig in CRASH_SIGNALS.iter() {
+            util::ll::sigaction(sig, &act, ptr::mut_null());
+        }
+    }
+}
+
+// write n in decimal into PID_BUF, NUL-terminated.
+unsafe fn format_pid(n: libc::pid_t) {
+    let mut digits = [0u8, ..20];
+    let mut len = 0;
+    let mut n = n as uint;
+    loop {
+        digits[len] = '0' as u8 + (n % 10) as u8;
+        len += 1;
+        n /= 10;
+        if n == 0 { break; }
+    }
+    for i in range(0, len) {
+        PID_BUF[i] = digits[len - 1 - i];
+    }
+    PID_BUF[len] = 0;
+}
+
+#[fixed_stack_segment]
+extern "C" fn crash_handler(sig: c_int) {
+    unsafe {
+        ll::ftruncate(BT_FD, 0);
+        ll::lseek(BT_FD, 0, ll::SEEK_SET);
+        format_pid(libc::getpid());
+
+        // gdb waits on this pipe until we have allowed it to attach.
+        let mut sync = [0 as c_int, ..2];
+        if libc::pipe(vec::raw::to_mut_ptr(sync)) == 0 {
+            let child = libc::fork();
+            if child == 0 {
+                libc::close(sync[1]);
+                let mut b = 0u8;
+                libc::read(sync[0], cast::transmute(&mut b), 1);
+                libc::dup2(BT_FD, 1);
+                libc::dup2(BT_FD, 2);
+                let argv: [*c_char, ..8] = [
+                    GDB,
+                    vec::raw::to_ptr(BATCH) as *c_char,
+                    vec::raw::to_ptr(NX) as *c_char,
+                    vec::raw::to_ptr(PID_FLAG) as *c_char,
+                    &PID_BUF[0] as *u8 as *c_char,
+                    vec::raw::to_ptr(EX) as *c_char,
+                    vec::raw::to_ptr(BT_ALL) as *c_char,
+                    0 as *c_char,
+                ];
+                ll::execvp(GDB, vec::raw::to_ptr(argv));
+                ll::_exit(127);
+            }
+            if child > 0 {
+                util::ll::prctl(ll::PR_SET_PTRACER, child as libc::c_ulong);
+                libc::close(sync[0]);
+                libc::close(sync[1]);
+                let mut status = 0 as c_int;
+                libc::funcs::posix01::wait::waitpid(child, &mut status, 0);
+            }
+        }
+
+        // and die of it, as if we had never caught it: the handler is
+        // already reset, and the signal arrives once we return.
+        util::ll::kill(libc::getpid(), sig);
+    }
+}
+
+/// In CUR, after TRY crashed: the backtrace gdb left, if any.
+#[fixed_stack_segment]
+pub fn take(bt_fd: c_int) -> Option<~str> {
+    if bt_fd < 0 {
+        return None;
+    }
+    let mut text = ~[];
+    unsafe {
+        ll::lseek(bt_fd, 0, ll::SEEK_SET);
+        loop {
+            let mut buf = vec::from_elem(4096, 0u8);
+            let n = do buf.as_mut_buf |p, len| {
+                libc::read(bt_fd, p as *mut c_void, len as libc::size_t)
+            };
+            if n <= 0 {
+                break;
+            }
+            text.push_all(buf.slice_to(n as uint));
+        }
+        ll::ftruncate(bt_fd, 0);
+        ll::lseek(bt_fd, 0, ll::SEEK_SET);
+    }
+    if text.len() == 0 {
+        return None;
+    }
+    Some(util::lossy(text))
+}
+
+/// The stack frames in gdb's output: the lines that start with #.
+pub fn frames(bt: &str) -> ~[~str] {
+    bt.line_iter().filter(|l| l.starts_with("#")).map(|l| l.to_owned()).collect()
+}
+
+/// Whether sig is one of the CRASH_SIGNALS.
+pub fn is_crash(sig: c_int) -> bool {
+    CRASH_SIGNALS.contains(&sig)
+}
@@ -0,0 +1,355 @@
+/**
+ *  editline: VISOR's line editor, a thin binding to the libedit
+ *  sources vendored under libedit/.
+ *
+ *  Rather than let libedit keep its own copy of the history, we hand
+ *  it a history function (hist_fn below) that walks Visor.cmd directly.
+ *  That way libedit's search.c (ctrl-r incremental search, vi-mode
+ *  '/' and '?') works over exactly what VISOR has recorded, and we can
+ *  restrict it to the "correct history" that .c shows.
+ **/
+
+use std::{cast, libc, ptr, str};
+use std::c_str::CString;
+use std::libc::{c_char, c_int, c_void};
+
+pub mod ll {
+    use std::libc::{c_char, c_int, c_void};
+    use std::libc::types::common::c95::{FILE};
+
+    pub struct LineInfo {
+        buffer: *c_char,
+        cursor: *c_char,
+        lastchar: *c_char,
+    }
+
+    pub struct HistEvent {
+        num: c_int,
+        str: *c_char,
+    }
+
+    // el_set() operations, from libedit/libedit/histedit.h
+    pub static EL_PROMPT:   c_int = 0;
+    pub static EL_EDITOR:   c_int = 2;
+    pub static EL_SIGNAL:   c_int = 3;
+    pub static EL_BIND:     c_int = 4;
+    pub static EL_HIST:     c_int = 10;
+    pub static EL_RPROMPT:  c_int = 12;
+    // EL_REFRESHFN is ours alone: ask el_refreshfn_op() for it.
+
+    // history() operations, from libedit/libedit/histedit.h
+    pub static H_FIRST:     c_int = 3;
+    pub static H_LAST:      c_int = 4;
+    pub static H_PREV:      c_int = 5;
+    pub static H_NEXT:      c_int = 6;
+
+    #[link_args = "-ledit"]
+    extern {
+        pub fn el_init(prog: *c_char, fin: *FILE, fout: *FILE, ferr: *FILE) -> *c_void;
+        pub fn el_end(el: *c_void);
+        pub fn el_gets(el: *c_void, count: *mut c_int) -> *c_char;
+        pub fn el_set(el: *c_void, op: c_int, ...) -> c_int;
+        pub fn el_source(el: *c_void, file: *c_char) -> c_int;
+        pub fn el_line(el: *c_void) -> *LineInfo;
+    }
+
+    #[link_args = "-ldl"]
+    extern {
+        pub fn dlsym(handle: *c_void, symbol: *c_char) -> *c_void;
+    }
+}
+
+// the el_set() op for the refresh hook, if the libedit we were linked
+// against is the vendored one, which has it; None for a system libedit.
+#[fixed_stack_segment]
+fn refreshfn_op() -> Option<c_int> {
+    unsafe {
+        // a null handle is RTLD_DEFAULT: the program and its libraries.
+        let f = do "el_refreshfn_op".with_c_str |s| { ll::dlsym(ptr::null(), s) };
+        if f.is_null() {
+            return None;
+        }
+        let op: extern "C" fn() -> c_int = cast::transmute(f);
+        Some(op())
+    }
+}
+
+/// The history as libedit sees it: a view onto Visor.cmd and
+/// Visor.failed, newest entry first.
+struct HistView {
+    cmd: *~[~str],
+    failed: *~[bool],
+
+    /// when set, entries that failed are invisible to search and
+    /// up-arrow, i.e. we walk the same history that .c prints.
+    correct_only: bool,
+
+    /// indices into cmd of the visible entries, newest first.
+    visible: ~[uint],
+    cstrs: ~[CString],
+    /// position in visible of the entry libedit last asked for.
+    cursor: uint,
+    /// Visor.cmd length when visible was last rebuilt.
+    seen: uint,
+
+    prompt: CString,
+    /// the last colorized line handed to libedit's refresh hook.
+    colored: CString,
+    ok_tag: CString,
+    failed_tag: CString,
+    no_tag: CString,
+}
+
+impl HistView {
+    fn cmds<'a>(&'a self) -> &'a ~[~str] {
+        unsafe { cast::transmute(self.cmd) }
+    }
+
+    fn fails<'a>(&'a self) -> &'a ~[bool] {
+        unsafe { cast::transmute(self.failed) }
+    }
+
+    fn entry_failed(&self, i: uint) -> bool {
+        let failed = self.fails();
+        i < failed.len() && failed[i]
+    }
+
+    // rebuild the visible list if VISOR has recorded more commands
+    // since we last looked, or if correct_only was toggled.
+    fn refresh(&mut self) {
+        let n = self.cmds().len();
+        if n == self.seen {
+            return;
+        }
+        let mut visible = ~[];
+        let mut cstrs = ~[];
+        let mut i = n;
+        while i > 0 {
+            i -= 1;
+            if self.correct_only && self.entry_failed(i) {
+                continue;
+            }
+            visible.push(i);
+            cstrs.push(self.cmds()[i].to_c_str());
+        }
+        self.visible = visible;
+        self.cstrs = cstrs;
+        self.seen = n;
+        self.cursor = 0;
+    }
+
+    fn set_event(&mut self, pos: uint, ev: *mut ll::HistEvent) -> c_int {
+        if pos >= self.visible.len() {
+            return -1;
+        }
+        self.cursor = pos;
+        unsafe {
+            (*ev).num = (self.visible[pos] + 1) as c_int;
+            (*ev).str = self.cstrs[pos].with_ref(|p| p);
+        }
+        0
+    }
+
+    // the tag shown in the right prompt: whether the line being
+    // edited (or the match being shown by an incremental search)
+    // is a history entry that succeeded or failed.
+    fn tag(&self, line: &str) -> *c_char {
+        if self.cursor < self.visible.len() {
+            let i = self.visible[self.cursor];
+            let c = self.cmds()[i].as_slice();
+            if c.len() > 0 && line.starts_with(c) {
+                return if self.entry_failed(i) {
+                    self.failed_tag.with_ref(|p| p)
+                } else {
+                    self.ok_tag.with_ref(|p| p)
+                };
+            }
+        }
+        self.no_tag.with_ref(|p| p)
+    }
+}
+
+// libedit's prompt functions get only the EditLine, so the one
+// HistView in use is reachable from here.
+static mut ACTIVE: *mut HistView = 0 as *mut HistView;
+
+// hist_fun_t: libedit calls this to walk the history.
+// FIRST is the newest entry, NEXT moves toward older entries.
+extern "C" fn hist_fn(hv: *c_void, ev: *mut ll::HistEvent, op: c_int, _arg: *c_void) -> c_int {
+    let view: &mut HistView = unsafe { cast::transmute(hv) };
+    view.refresh();
+    match op {
+        ll::H_FIRST => view.set_event(0, ev),
+        ll::H_LAST  => {
+            let n = view.visible.len();
+            if n == 0 { -1 } else { view.set_event(n - 1, ev) }
+        },
+        ll::H_NEXT  => view.set_event(view.cursor + 1, ev),
+        ll::H_PREV  => {
+            if view.cursor == 0 { -1 } else { view.set_event(view.cursor - 1, ev) }
+        },
+        _ => -1,
+    }
+}
+
+extern "C" fn prompt_fn(_el: *c_void) -> *c_char {
+    unsafe {
+        if ACTIVE.is_null() {
+            return ptr::null();
+        }
+        (*ACTIVE).prompt.with_ref(|p| p)
+    }
+}
+
+#[fixed_stack_segment]
+unsafe fn current_line(el: *c_void) -> ~str {
+    let li = ll::el_line(el);
+    let len = (*li).lastchar as uint - (*li).buffer as uint;
+    str::raw::from_buf_len((*li).buffer as *u8, len)
+}
+
+#[fixed_stack_segment]
+extern "C" fn rprompt_fn(el: *c_void) -> *c_char {
+    unsafe {
+        if ACTIVE.is_null() {
+            return ptr::null();
+        }
+        (*ACTIVE).tag(current_line(el))
+    }
+}
+
+// el_rfunc_t: libedit's refresh hook, see re_highlight() in
+// libedit/libedit/refresh.c. Lines with control characters in them
+// (an incremental search prompt, tabs) display wider than their byte
+// count, so those are left plain.
+#[fixed_stack_segment]
+extern "C" fn highlight_fn(el: *c_void) -> *c_char {
+    unsafe {
+        if ACTIVE.is_null() {
+            return ptr::null();
+        }
+        let line = current_line(el);
+        if line.as_bytes().iter().any(|&b| b < 0x20 || b == 0x7f) {
+            return ptr::null();
+        }
+        (*ACTIVE).colored = super::highlight::colorize(line).to_c_str();
+        (*ACTIVE).colored.with_ref(|p| p)
+    }
+}
+
+pub struct Editor {
+    el: *c_void,
+    hist: ~HistView,
+}
+
+impl Editor {
+    /// Set up libedit on stdin/stdout with emacs bindings and ctrl-r
+    /// bound to incremental search back through cmd. cmd and failed
+    /// must outlive the Editor; VISOR owns both.
+    #[fixed_stack_segment]
+    pub fn new(prog: &str, prompt: &str, cmd: &~[~str], failed: &~[bool]) -> Editor {
+        let mut hist = ~HistView {
+            cmd: cmd as *~[~str],
+            failed: failed as *~[bool],
+            correct_only: false,
+            visible: ~[],
+            cstrs: ~[],
+            cursor: 0,
+            seen: 0,
+            prompt: prompt.to_c_str(),
+            colored: "".to_c_str(),
+            ok_tag: "[ok]".to_c_str(),
+            failed_tag: "[failed]".to_c_str(),
+            no_tag: "".to_c_str(),
+        };
+
+        unsafe {
+            let fin = do "r".with_c_str |m| { libc::fdopen(0, m) };
+            let fout = do "w".with_c_str |m| { libc::fdopen(1, m) };
+            let ferr = do "w".with_c_str |m| { libc::fdopen(2, m) };
+            let el = do prog.with_c_str |p| { ll::el_init(p, fin, fout, ferr) };
+            if el.is_null() {
+                fail2!("editline: el_init failed");
+            }
+
+            ACTIVE = &mut *hist as *mut HistView;
+            let hv: *c_void = cast::transmute(&mut *hist as *mut HistView);
+
+            ll::el_set(el, ll::EL_PROMPT, prompt_fn);
+            ll::el_set(el, ll::EL_RPROMPT, rprompt_fn);
+            do "emacs".with_c_str |e| { ll::el_set(el, ll::EL_EDITOR, e); }
+            ll::el_set(el, ll::EL_SIGNAL, 1 as c_int);
+            ll::el_set(el, ll::EL_HIST, hist_fn, hv);
+            do "^R".with_c_str |k| {
+                do "em-inc-search-prev".with_c_str |f| {
+                    ll::el_set(el, ll::EL_BIND, k, f, ptr::null::<c_char>());
+                }
+            }
+            // let the user's ~/.editrc override any of the above
+            ll::el_source(el, ptr::null());
+
+            Editor { el: el, hist: hist }
+        }
+    }
+
+    /// Read one line, without its trailing newline. None on EOF.
+    #[fixed_stack_segment]
+    pub fn gets(&mut self) -> Option<~str> {
+        // VISOR may have swapped in another branch's history of the
+        // same length, so always rebuild once per line.
+        self.hist.seen = -1 as uint;
+        self.hist.refresh();
+        let mut count = 0 as c_int;
+        unsafe {
+            let line = ll::el_gets(self.el, &mut count);
+            if line.is_null() || count <= 0 {
+                return None;
+            }
+            let s = str::raw::from_buf_len(line as *u8, count as uint);
+            Some(s.trim_right_chars(&'\n').to_owned())
+        }
+    }
+
+    /// Change the prompt, e.g. for the post-mortem prompt.
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.hist.prompt = prompt.to_c_str();
+    }
+
+    /// Turn syntax highlighting of the input line on or off. False if
+    /// this libedit has no refresh hook, and so no highlighting.
+    #[fixed_stack_segment]
+    pub fn set_highlight(&mut self, on: bool) -> bool {
+        let op = match refreshfn_op() {
+            Some(op) => op,
+            None => return !on,
+        };
+        unsafe {
+            if on {
+                ll::el_set(self.el, op, highlight_fn);
+            } else {
+                ll::el_set(self.el, op, ptr::null::<c_void>());
+            }
+        }
+        true
+    }
+
+    /// Toggle searching only the commands that succeeded; returns
+    /// the new setting.
+    pub fn toggle_correct_only(&mut self) -> bool {
+        self.hist.correct_only = !self.hist.correct_only;
+        // force a rebuild of the visible list on next use
+        self.hist.seen = -1 as uint;
+        self.hist.correct_only
+    }
+}
+
+impl Drop for Editor {
+    #[fixed_stack_segment]
+    fn drop(&mut self) {
+        unsafe {
+            ACTIVE = 0 as *mut HistView;
+            ll::el_end(self.el);
+        }
+    }
+}
+
@@ -0,0 +1,312 @@
+/**
+ *  export: turn a session into a program, for .export file.rs.
+ *
+ *  The snippets that succeeded, as .c shows them, are cut into their
+ *  top-level pieces. Items (fns, structs, impls and the like) go to the
+ *  top of the crate, extern mods and uses first, as rustc wants them;
+ *  statements go into main() in session order; and an expression that
+ *  ends a snippet, whose value the repl would show, is printed with
+ *  println!. The cutting is lexical, on lexer's tokens: brackets,
+ *  strings and comments are told apart, but nothing is parsed.
+ *
+ *  An existing file is only overwritten when asked to (-f). A
+ *  Cargo.toml goes next to the file, unless there is one already.
+ *
+ *  The same cutting finds the extern mods and uses that TRY keeps for
+ *  checking items on their own (see unit).
+ **/
+
+use std::{io, os, path};
+
+use lexer;
+
+enum Piece {
+    Item(~str),
+    Stmt(~str),
+    Expr(~str),
+}
+
+static ITEM_WORDS: &'static [&'static str] = &[
+    "fn", "pub", "priv", "struct", "enum", "impl", "trait", "use", "static",
+    "mod", "extern", "type", "macro_rules",
+];
+
+// statements that take a block and need no semicolon after it.
+static BLOCK_WORDS: &'static [&'static str] = &[
+    "if", "match", "while", "loop", "for", "do", "unsafe",
+];
+
+// expressions that are always (), not worth printing.
+static UNIT_WORDS: &'static [&'static str] = &["while", "loop", "for"];
+
+/// The program made of snippets, in order.
+pub fn program(snippets: &[~str]) -> ~str {
+    let mut externs = ~[];
+    let mut uses = ~[];
+    let mut items = ~[];
+    let mut body = ~[];
+    for s in snippets.iter() {
+        for p in classify(*s).move_iter() {
+            match p {
+                Item(text) => if is_extern_mod(text) {
+                    externs.push(text);
+                } else if is_use(text) {
+                    uses.push(text);
+                } else {
+                    items.push(text);
+                },
+                Stmt(text) => body.push(text),
+                Expr(text) => body.push(format!("println!(\"\\{:?\\}\", ({:s}));", text)),
+            }
+        }
+    }
+
+    let mut out = ~"// exported from a rustxi session.\n\n";
+    for group in [externs, uses].iter() {
+        if group.len() > 0 {
+            out.push_str(group.connect("\n"));
+            out.push_str("\n\n");
+        }
+    }
+    for i in items.iter() {
+        out.push_str(*i);
+        out.push_str("\n\n");
+    }
+    out.push_str("fn main() {\n");
+    for s in body.iter() {
+        for line in s.line_iter() {
+            if line.len() > 0 {
+                out.push_str("    ");
+                out.push_str(line);
+            }
+            out.push_str("\n");
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The extern mods and uses in a snippet, in order.
+pub fn view_items(code: &str) -> ~[~str] {
+    classify(code).move_iter().filter_map(|p| match p {
+        Item(ref text) if is_extern_mod(*text) || is_use(*text) => Some(text.clone()),
+        _ => None,
+    }).collect()
+}
+
+/// The crate compile::check type-checks: items, with the extern mods and
+/// uses they may need first, as rustc wants them.
+pub fn unit(views: &[~str], items: &[~str]) -> ~str {
+    let (externs, uses) = views.partitioned(|v| is_extern_mod(*v));
+    let mut out = ~"";
+    for v in externs.iter().chain(uses.iter()).chain(items.iter()) {
+        out.push_str(*v);
+        out.push_str("\n\n");
+    }
+    out
+}
+
+fn is_extern_mod(text: &str) -> bool {
+    let start = code_start(text);
+    first_word(start) == "extern" && second_word(start) == "mod"
+}
+
+fn is_use(text: &str) -> bool {
+    first_word(code_start(text)) == "use"
+}
+
+/// A Cargo manifest for a program in file, with a bin target called name.
+pub fn manifest(name: &str, file: &str) -> ~str {
+    format!("[package]\nname = \"{:s}\"\nversion = \"0.1.0\"\n\n\
+             [[bin]]\nname = \"{:s}\"\npath = \"{:s}\"\n", name, name, file)
+}
+
+/// Write the program to path, and a Cargo.toml beside it if there is
+/// none. If path exists, only with force. The lines returned say what
+/// was done.
+pub fn write(path: &str, snippets: &[~str], force: bool) -> Result<~[~str], ~str> {
+    let p = path::Path(path);
+    let (stem, file) = match (p.filestem(), p.filename()) {
+        (Some(s), Some(f)) => (s, f),
+        _ => return Err(format!("{:s}: not a file name", path)),
+    };
+    if !file.ends_with(".rs") {
+        return Err(format!("{:s}: the name should end in .rs", path));
+    }
+    if !force && os::path_exists(&p) {
+        return Err(format!("{:s} exists; .export -f {:s} overwrites it", path, path));
+    }
+    let mut done = ~[];
+    match write_file(&p, program(snippets)) {
+        Ok(()) => done.push(format!("wrote {:u} snippets to {:s}", snippets.len(), path)),
+        Err(e) => return Err(e),
+    }
+    let mp = p.dir_path().push("Cargo.toml");
+    if os::path_exists(&mp) {
+        done.push(format!("left the existing {:s} alone", mp.to_str()));
+    } else {
+        match write_file(&mp, manifest(crate_name(stem), file)) {
+            Ok(()) => done.push(format!("wrote {:s}", mp.to_str())),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(done)
+}
+
+fn write_file(p: &path::Path, text: &str) -> Result<(), ~str> {
+    match io::file_writer(p, [io::Create, io::Truncate]) {
+        Ok(w) => { w.write_str(text); Ok(()) },
+        Err(e) => Err(format!("{:s}: {:s}", p.to_str(), e)),
+    }
+}
+
+// cargo's rules: letters, digits, - and _, not starting with a digit.
+fn crate_name(stem: &str) -> ~str {
+    let mut name = ~"";
+    for c in stem.iter() {
+        name.push_char(if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' });
+    }
+    if name.len() == 0 || name.char_at(0).is_digit() {
+        name = format!("session_{:s}", name);
+    }
+    name
+}
+
+// cut a snippet into pieces and sort them: the last piece is an
+// expression if nothing ends it.
+fn classify(code: &str) -> ~[Piece] {
+    let pieces = split(code);
+    let n = pieces.len();
+    let mut out = ~[];
+    for (i, p) in pieces.move_iter().enumerate() {
+        let start = code_start(p);
+        let w = first_word(start);
+        if start.len() == 0 {
+            // only comments.
+            out.push(Stmt(p));
+        } else if is_item(start) {
+            out.push(Item(p));
+        } else if i + 1 == n && !p.ends_with(";") && !p.ends_with("}")
+                  && !UNIT_WORDS.contains(&w) {
+            out.push(Expr(p));
+        } else if i + 1 == n && p.ends_with("}") && !BLOCK_WORDS.contains(&w) {
+            // a struct literal, or some other expression ending in a brace.
+            out.push(Expr(p));
+        } else {
+            out.push(Stmt(p));
+        }
+    }
+    out
+}
+
+fn is_item(start: &str) -> bool {
+    let w = first_word(start);
+    ITEM_WORDS.contains(&w) || (w == "unsafe" && second_word(start) == "fn")
+}
+
+// split at semicolons outside brackets, and after a closing brace that
+// ends an item or a block statement.
+fn split(code: &str) -> ~[~str] {
+    let mut out = ~[];
+    let mut start = 0u;
+    let mut depth = 0;
+    for t in lexer::tokenize(code).iter() {
+        match t.kind {
+            lexer::Open => depth += 1,
+            lexer::Close => {
+                depth -= 1;
+                if depth == 0 && code.slice(t.lo, t.hi) == "}"
+                    && ends_at_brace(code.slice(start, t.hi), code.slice_from(t.hi)) {
+                    push_piece(&mut out, code.slice(start, t.hi));
+                    start = t.hi;
+                }
+            },
+            lexer::Plain if depth == 0 && code.slice(t.lo, t.hi) == ";" => {
+                push_piece(&mut out, code.slice(start, t.hi));
+                start = t.hi;
+            },
+            _ => (),
+        }
+    }
+    if start < code.len() {
+        push_piece(&mut out, code.slice_from(start));
+    }
+    out
+}
+
+fn push_piece(out: &mut ~[~str], p: &str) {
+    let p = p.trim();
+    if p.len() > 0 {
+        out.push(p.to_owned());
+    }
+}
+
+fn ends_at_brace(piece: &str, rest: &str) -> bool {
+    let start = code_start(piece);
+    let w = first_word(start);
+    let block = is_item(start) || start.starts_with("{") || BLOCK_WORDS.contains(&w);
+    block && first_word(code_start(rest)) != "else"
+}
+
+// text after leading whitespace, comments and attributes.
+fn code_start<'a>(text: &'a str) -> &'a str {
+    let mut s = text.trim_left();
+    loop {
+        if s.starts_with("//") {
+            s = match s.find('\n') {
+                Some(n) => s.slice_from(n + 1).trim_left(),
+                None => "",
+            };
+        } else if s.starts_with("/*") {
+            s = match s.find_str("*/") {
+                Some(n) => s.slice_from(n + 2).trim_left(),
+                None => "",
+            };
+        } else if s.starts_with("#[") {
+            s = match s.find(']') {
+                Some(n) => s.slice_from(n + 1).trim_left(),
+                None => "",
+            };
+        } else {
+            return s;
+        }
+    }
+}
+
+fn first_word<'a>(s: &'a str) -> &'a str {
+    let end = s.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or_default(s.len());
+    s.slice_to(end)
+}
+
+fn second_word<'a>(s: &'a str) -> &'a str {
+    first_word(s.slice_from(first_word(s).len()).trim_left())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{unit, view_items};
+
+    #[test]
+    fn uses_from_earlier_snippets_reach_the_unit() {
+        let mut views = ~[];
+        for s in [~"use std::hashmap::HashMap;\nlet m: HashMap<int, int> = HashMap::new();",
+                  ~"extern mod extra;\nfn ten() -> int { 10 }"].iter() {
+            for v in view_items(*s).move_iter() {
+                if !views.contains(&v) {
+                    views.push(v);
+                }
+            }
+        }
+        assert_eq!(views, ~[~"use std::hashmap::HashMap;", ~"extern mod extra;"]);
+
+        let f = ~"fn table() -> HashMap<int, int> { HashMap::new() }";
+        assert_eq!(unit(views, [f]),
+                   ~"extern mod extra;\n\nuse std::hashmap::HashMap;\n\n\
+                     fn table() -> HashMap<int, int> { HashMap::new() }\n\n");
+    }
+
+    #[test]
+    fn statements_are_no_view_items() {
+        assert_eq!(view_items("let used = 1;\nfn f() { use_it(); }"), ~[]);
+    }
+}
@@ -0,0 +1,36 @@
+/**
+ *  highlight: colorize a line of Rust as the user types it, token by
+ *  token as lexer cuts it.
+ **/
+
+use lexer;
+use lexer::{Comment, Keyword, Lifetime, Literal, Plain, Open, Close, TokenKind};
+
+static RESET: &'static str = "\x1b[0m";
+
+fn color(kind: TokenKind) -> &'static str {
+    match kind {
+        Plain | Open | Close => "",
+        Keyword  => "\x1b[1;35m",
+        Literal  => "\x1b[32m",
+        Lifetime => "\x1b[36m",
+        Comment  => "\x1b[34m",
+    }
+}
+
+/// The line with ANSI color escapes around keywords, literals,
+/// lifetimes and comments. The visible characters are unchanged.
+pub fn colorize(line: &str) -> ~str {
+    let mut out = ~"";
+    for t in lexer::tokenize(line).iter() {
+        let text = line.slice(t.lo, t.hi);
+        if color(t.kind).len() == 0 {
+            out.push_str(text);
+        } else {
+            out.push_str(color(t.kind));
+            out.push_str(text);
+            out.push_str(RESET);
+        }
+    }
+    out
+}
@@ -0,0 +1,209 @@
+/**
+ *  lexer: cut Rust source into tokens, for the places that need to
+ *  know where strings, comments and brackets are without parsing:
+ *  highlighting the input line, cutting a script into chunks and a
+ *  snippet into pieces for .export.
+ *
+ *  It is deliberately forgiving: the input is often a half-typed line,
+ *  so an unterminated string or block comment simply runs to the end
+ *  of the text instead of being an error. A line comment runs to the
+ *  end of its line.
+ **/
+
+#[deriving(Eq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    Literal,
+    Lifetime,
+    Comment,
+    /// one of ( [ {
+    Open,
+    /// one of ) ] }
+    Close,
+}
+
+pub struct Token {
+    kind: TokenKind,
+    lo: uint,
+    hi: uint,
+}
+
+static KEYWORDS: &'static [&'static str] = &[
+    "as", "break", "continue", "do", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "mut",
+    "once", "priv", "pub", "ref", "return", "self", "static", "struct",
+    "super", "trait", "true", "type", "unsafe", "use", "while",
+];
+
+// bytes of multibyte utf8 sequences count as identifier characters,
+// so a token boundary never falls inside a character.
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic() || c as u32 >= 0x80
+}
+
+fn is_ident_char(c: char) -> bool {
+    c == '_' || c.is_alphanumeric() || c as u32 >= 0x80
+}
+
+struct Lexer<'self> {
+    src: &'self [u8],
+    pos: uint,
+}
+
+impl<'self> Lexer<'self> {
+    fn peek(&self, n: uint) -> char {
+        if self.pos + n < self.src.len() {
+            self.src[self.pos + n] as char
+        } else {
+            '\0'
+        }
+    }
+
+    fn eof(&self) -> bool {
+        self.pos >= self.src.len()
+    }
+
+    fn bump_while(&mut self, f: &fn(char) -> bool) {
+        while !self.eof() && f(self.peek(0)) {
+            self.pos += 1;
+        }
+    }
+
+    // "..." with backslash escapes; pos is on the opening quote.
+    fn string(&mut self) {
+        self.pos += 1;
+        while !self.eof() {
+            match self.peek(0) {
+                '\\' => self.pos += 2,
+                '"' => { self.pos += 1; return; },
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    // r"..." or r##"..."##; pos is on the r.
+    fn raw_string(&mut self) {
+        self.pos += 1;
+        let mut hashes = 0u;
+        while self.peek(0) == '#' {
+            hashes += 1;
+            self.pos += 1;
+        }
+        self.pos += 1;
+        while !self.eof() {
+            if self.peek(0) == '"' {
+                let mut n = 0u;
+                while n < hashes && self.peek(n + 1) == '#' {
+                    n += 1;
+                }
+                if n == hashes {
+                    self.pos += hashes + 1;
+                    return;
+                }
+            }
+            self.pos += 1;
+        }
+    }
+
+    // /* ... */, which nest in Rust; pos is on the slash.
+    fn block_comment(&mut self) {
+        self.pos += 2;
+        let mut depth = 1u;
+        while !self.eof() && depth > 0 {
+            if self.peek(0) == '/' && self.peek(1) == '*' {
+                depth += 1;
+                self.pos += 2;
+            } else if self.peek(0) == '*' && self.peek(1) == '/' {
+                depth -= 1;
+                self.pos += 2;
+            } else {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // a quote starts either a char literal or a lifetime: 'a' vs 'a.
+    fn quote(&mut self) -> TokenKind {
+        if self.peek(1) == '\\' {
+            self.pos += 2;
+            self.bump_while(|c| c != '\'');
+            self.pos += 1;
+            return Literal;
+        }
+        if self.peek(2) == '\'' {
+            self.pos += 3;
+            return Literal;
+        }
+        self.pos += 1;
+        self.bump_while(|c| is_ident_char(c));
+        Lifetime
+    }
+
+    // 42, 0x2a, 1_000u, 1.5f64 ...
+    fn number(&mut self) {
+        self.bump_while(|c| is_ident_char(c));
+        if self.peek(0) == '.' && self.peek(1).is_digit() {
+            self.pos += 1;
+            self.bump_while(|c| is_ident_char(c));
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        if self.eof() {
+            return None;
+        }
+        let lo = self.pos;
+        let c = self.peek(0);
+        let kind = if c == '/' && self.peek(1) == '/' {
+            self.bump_while(|c| c != '\n');
+            Comment
+        } else if c == '/' && self.peek(1) == '*' {
+            self.block_comment();
+            Comment
+        } else if c == '"' {
+            self.string();
+            Literal
+        } else if c == 'r' && (self.peek(1) == '"' || (self.peek(1) == '#' &&
+                                                       (self.peek(2) == '"' || self.peek(2) == '#'))) {
+            self.raw_string();
+            Literal
+        } else if c == '\'' {
+            self.quote()
+        } else if c.is_digit() {
+            self.number();
+            Literal
+        } else if is_ident_start(c) {
+            self.bump_while(|c| is_ident_char(c));
+            let word = ::std::str::from_utf8(self.src.slice(lo, self.pos));
+            if KEYWORDS.contains(&word.as_slice()) { Keyword } else { Plain }
+        } else if "([{".contains_char(c) {
+            self.pos += 1;
+            Open
+        } else if ")]}".contains_char(c) {
+            self.pos += 1;
+            Close
+        } else {
+            self.pos += 1;
+            Plain
+        };
+        if self.pos > self.src.len() {
+            self.pos = self.src.len();
+        }
+        Some(Token { kind: kind, lo: lo, hi: self.pos })
+    }
+}
+
+/// Split text into tokens. Every byte of it belongs to exactly one
+/// token, so the tokens can be re-joined losslessly.
+pub fn tokenize(text: &str) -> ~[Token] {
+    let mut lexer = Lexer { src: text.as_bytes(), pos: 0 };
+    let mut toks = ~[];
+    loop {
+        match lexer.next() {
+            Some(t) => toks.push(t),
+            None => break,
+        }
+    }
+    toks
+}
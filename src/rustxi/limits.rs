@@ -0,0 +1,232 @@
+/**
+ *  limits: keep a runaway snippet from taking the machine down, and
+ *  every rustxi process with it.
+ *
+ *  TRY lowers its soft rlimits (RLIMIT_AS, RLIMIT_CPU, RLIMIT_NOFILE,
+ *  RLIMIT_FSIZE) just before running code, and puts them back if it
+ *  succeeds and becomes CUR. Optionally the whole CUR/TRY tree also
+ *  lives in a cgroup v2 subtree of its own, with memory.max, cpu.max
+ *  and pids.max set from the rc file.
+ *
+ *  When TRY fails, classify names the limit it ran into, on evidence
+ *  only: the signal the kernel sends for cpu and fsize, the cgroup's
+ *  memory.events and pids.events counting up while TRY ran, or the
+ *  tracer having seen an allocation refused. Without a cgroup or the
+ *  tracer (which the sandbox rules out), an address space overrun is
+ *  reported as the crash it caused.
+ **/
+
+use std::{io, os, path};
+use std::libc::{c_int};
+use std::libc::types::os::arch::posix88::{pid_t};
+
+use config::Config;
+use msg;
+use signum;
+use util;
+
+pub mod ll {
+    use std::libc::{c_int};
+
+    pub struct rlimit {
+        rlim_cur: u64,
+        rlim_max: u64,
+    }
+
+    // from /usr/include/x86_64-linux-gnu/bits/resource.h
+    pub static RLIMIT_CPU: c_int = 0;
+    pub static RLIMIT_FSIZE: c_int = 1;
+    pub static RLIMIT_NOFILE: c_int = 7;
+    pub static RLIMIT_AS: c_int = 9;
+
+    pub static RLIM_INFINITY: u64 = -1 as u64;
+
+    extern {
+        pub fn getrlimit(resource: c_int, rlim: *mut rlimit) -> c_int;
+        pub fn setrlimit(resource: c_int, rlim: *rlimit) -> c_int;
+    }
+}
+
+/// The soft limits TRY replaced, to put back when it becomes CUR.
+pub struct Saved {
+    old: ~[(c_int, u64)],
+}
+
+// the (resource, soft limit) pairs to apply; 0 in the config means
+// no limit.
+fn wanted(config: &Config) -> ~[(c_int, u64)] {
+    let mut v = ~[];
+    if config.limit_as > 0 { v.push((ll::RLIMIT_AS, config.limit_as)); }
+    if config.limit_cpu > 0 { v.push((ll::RLIMIT_CPU, config.limit_cpu)); }
+    if config.limit_nofile > 0 { v.push((ll::RLIMIT_NOFILE, config.limit_nofile)); }
+    if config.limit_fsize > 0 { v.push((ll::RLIMIT_FSIZE, config.limit_fsize)); }
+    v
+}
+
+/// Lower TRY's soft limits as configured, never above the hard ones.
+#[fixed_stack_segment]
+pub fn apply(config: &Config) -> Saved {
+    let mut old = ~[];
+    for &(res, want) in wanted(config).iter() {
+        unsafe {
+            let mut rl = ll::rlimit { rlim_cur: 0, rlim_max: 0 };
+            if ll::getrlimit(res, &mut rl) != 0 {
+                continue;
+            }
+            old.push((res, rl.rlim_cur));
+            let soft = if rl.rlim_max != ll::RLIM_INFINITY && want > rl.rlim_max {
+                rl.rlim_max
+            } else {
+                want
+            };
+            let new = ll::rlimit { rlim_cur: soft, rlim_max: rl.rlim_max };
+            if ll::setrlimit(res, &new) != 0 {
+                debug2!("{:d}: TRY: setrlimit({:d}) failed: {:s}",
+                       util::getpid() as int, res as int, os::last_os_error());
+            }
+        }
+    }
+    Saved { old: old }
+}
+
+/// Put back the soft limits apply() replaced.
+#[fixed_stack_segment]
+pub fn restore(saved: Saved) {
+    for &(res, soft) in saved.old.iter() {
+        unsafe {
+            let mut rl = ll::rlimit { rlim_cur: 0, rlim_max: 0 };
+            if ll::getrlimit(res, &mut rl) != 0 {
+                continue;
+            }
+            let new = ll::rlimit { rlim_cur: soft, rlim_max: rl.rlim_max };
+            ll::setrlimit(res, &new);
+        }
+    }
+}
+
+/// A cgroup v2 directory holding the CUR/TRY tree.
+pub struct Cgroup {
+    dir: path::Path,
+}
+
+impl Cgroup {
+    /// Make rustxi-<pid> under the configured parent cgroup and set its
+    /// limits. None if there is no cgroup configured, or we are not
+    /// allowed to make one; rustxi works without.
+    pub fn create(config: &Config) -> Option<Cgroup> {
+        let parent = match config.cgroup {
+            None => return None,
+            Some(ref p) => path::Path(*p),
+        };
+        let dir = parent.push(format!("rustxi-{:d}", util::getpid() as int));
+        if !os::make_dir(&dir, 0o755) {
+            io::stderr().write_line(format!("{:s}: cannot create cgroup {:s}: {:s}",
+                                            super::PROGRAM_NAME, dir.to_str(),
+                                            os::last_os_error()));
+            return None;
+        }
+        let cg = Cgroup { dir: dir };
+        if config.cgroup_memory_max > 0 {
+            cg.write("memory.max", config.cgroup_memory_max.to_str());
+        }
+        if config.cgroup_cpu_percent > 0 {
+            // quota and period, in microseconds
+            cg.write("cpu.max", format!("{:u} 100000", config.cgroup_cpu_percent * 1000));
+        }
+        if config.cgroup_pids_max > 0 {
+            cg.write("pids.max", config.cgroup_pids_max.to_str());
+        }
+        Some(cg)
+    }
+
+    fn write(&self, file: &str, value: &str) -> bool {
+        let p = self.dir.push(file);
+        match io::file_writer(&p, [io::Create, io::Truncate]) {
+            Ok(w) => { w.write_str(value); true },
+            Err(e) => {
+                debug2!("{:d}: cgroup: cannot write {:s}: {:s}",
+                       util::getpid() as int, p.to_str(), e);
+                false
+            },
+        }
+    }
+
+    /// Move pid, and so everything it forks later, into the cgroup.
+    pub fn add(&self, pid: pid_t) -> bool {
+        self.write("cgroup.procs", format!("{:d}", pid as int))
+    }
+
+    /// The cgroup's event counts so far.
+    pub fn events(&self) -> Events {
+        Events {
+            oom: self.count("memory.events", "oom"),
+            oom_kill: self.count("memory.events", "oom_kill"),
+            pids_max: self.count("pids.events", "max"),
+        }
+    }
+
+    // a "key count" line of an events file; 0 if there is none.
+    fn count(&self, file: &str, key: &str) -> uint {
+        let text = match io::read_whole_file_str(&self.dir.push(file)) {
+            Ok(t) => t,
+            Err(_) => return 0,
+        };
+        for line in text.line_iter() {
+            let words: ~[&str] = line.word_iter().collect();
+            if words.len() == 2 && words[0] == key {
+                return from_str::<uint>(words[1]).unwrap_or_default(0);
+            }
+        }
+        0
+    }
+
+    /// Remove the cgroup; it must be empty by now.
+    pub fn remove(&self) {
+        os::remove_dir(&self.dir);
+    }
+}
+
+/// What the cgroup has counted: times memory.max couldn't be kept to
+/// and the OOM killer fired, and forks refused by pids.max.
+pub struct Events {
+    oom: uint,
+    oom_kill: uint,
+    pids_max: uint,
+}
+
+/// Why TRY failed, from its wait status. before is the cgroup's
+/// events from before TRY ran, if there is a cgroup; alloc_failed,
+/// whether the tracer saw TRY refused memory.
+pub fn classify(status: c_int, cgroup: &Option<Cgroup>, before: &Option<Events>,
+                alloc_failed: bool) -> msg::Failure {
+    // a refused fork or allocation may end in any failure at all.
+    match (cgroup, before) {
+        (&Some(ref cg), &Some(ref b)) => {
+            let now = cg.events();
+            if now.oom > b.oom || now.oom_kill > b.oom_kill {
+                return msg::OverLimit(~"memory");
+            }
+            if now.pids_max > b.pids_max {
+                return msg::OverLimit(~"pids");
+            }
+        },
+        _ => (),
+    }
+    match util::term_signal(status) {
+        Some(sig) => {
+            if sig == signum::SIGXCPU {
+                return msg::OverLimit(~"cpu");
+            }
+            if sig == signum::SIGXFSZ {
+                return msg::OverLimit(~"fsize");
+            }
+            // out of address space, the allocator aborts. Other crashes
+            // abort too, so only when memory was in fact refused.
+            if alloc_failed && (sig == signum::SIGABRT || sig == signum::SIGSEGV) {
+                return msg::OverLimit(~"memory");
+            }
+            msg::Signaled(sig as int)
+        },
+        None => msg::Exited(util::exit_status(status).unwrap_or_default(0) as int),
+    }
+}
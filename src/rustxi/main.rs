@@ -14,20 +14,49 @@ extern mod extra;
 extern mod syntax;
 extern mod rustc;
 
-use std::{io, libc, os, vec, rt};
-use std::libc::{c_int, c_void};
+use std::{hashmap, io, libc, os, path, rt};
+use std::cell::Cell;
+use std::libc::{c_int};
 use std::cast;
 
 use callgraph::CallGraph;
 
+mod branch;
+mod capture;
 mod compile;
 mod callgraph;
+mod calls;
+mod config;
+mod crash;
+mod editline;
+mod export;
+mod highlight;
+mod lexer;
+mod limits;
+mod msg;
+mod postmortem;
+mod sandbox;
+mod script;
+mod snapshot;
 mod signum;
+mod trace;
+mod twice;
 mod util;
 
-static CODEBUF_SIZE: i64 = 4096;
 pub static PROGRAM_NAME: &'static str = "rustxi";
 
+// VISOR's exit status: fine, some script chunk failed, the script
+// could not be read, or CUR/TRY had to be killed at shutdown.
+static EXIT_OK: c_int = 0;
+static EXIT_FAILED: c_int = 1;
+static EXIT_NO_SCRIPT: c_int = 2;
+static EXIT_UNCLEAN: c_int = 3;
+
+// sandbox::DENIED_STATUS, 66, is TRY's exit status for a denied syscall.
+
+// how much of a crash's backtrace to show at the prompt.
+static BT_FRAMES: uint = 8;
+
 // help(), banner(), prompt():
 // generate user-facing help strings. Since these may be dynamic or
 // localized or both, these need to be function calls not constants.
@@ -39,10 +68,39 @@ fn help() -> &str {
     static HELP: &'static str = "\
 .?                   show help
 .q                   exit rustxi
-.h                   show line history
+.h [-v]              show line history; -v adds what each snippet printed
+.show N              reprint what snippet N printed
 .c                   correct history only
+.export [-f] file.rs write the correct history out as a program, with a Cargo.toml;
+                     -f to overwrite an existing file
+.r                   toggle ctrl-r search: all history / correct only
+.bt                  show the backtrace of the last crash
+.g                   list the fns, types, traits and impls in the call graph,
+                     each with what it uses
+.g f: a, b           tell the call graph that f calls a and b, defined yet or not
+.gd f                delete f; what calls it is broken until f is defined again
+.g? callers f        list what calls f; also callees, dependents (transitive
+                     callers) and deps (transitive callees)
+.g? cycles           list the groups of fns that call each other in a circle
+.g? unused           list the fns that nothing calls
+.g? broken           list the fns that call a deleted fn
+.g? unresolved       list the fns called but not defined yet
+.gdot file           write the call graph to file for Graphviz; .gjson file as JSON
+.twice               toggle running snippets twice to spot nondeterminism
+.postmortem          toggle keeping a failed snippet's state to inspect
+.abandon             leave post-mortem inspection, back to the last good state
+.undo                revert the last successful snippet
+.checkpoint [name]   name the current state; list checkpoints
+.goto name           return to a named checkpoint
+.branch name         keep a copy of the current state as a new branch
+.branches            list branches and their histories
+.switch name         continue in another branch
 .s file              source file -- XXTODO
-.. {commands}        system(commands) -- XXTODO";
+.. {commands}        system(commands) -- XXTODO
+
+rustxi [--sandbox] [--results file] [script.rs]
+                     a script, or input piped in, runs in batch mode; with
+                     --results, one line of JSON per chunk goes to file";
 
     HELP
 }
@@ -60,6 +118,12 @@ fn prompt() -> &str {
     PROMPT
 }
 
+#[inline]
+fn post_mortem_prompt() -> &str {
+    static PROMPT: &'static str = "post-mortem> ";
+    PROMPT
+}
+
 #[fixed_stack_segment]
 #[abi = "cdecl"]
 fn ctrl_c_handler(_signum: c_int) {
@@ -80,8 +144,69 @@ struct Visor {
     // history of fail/success
     failed: ~[bool],
 
-    /// function dependency graph
-    callgraph: callgraph::BothWayGraph,
+    /// what each command printed, when output is captured.
+    outputs: ~[capture::Output],
+
+    /// what the snippets so far defined.
+    defs: Defs,
+
+    /// in VISOR: the call graph changes each command made, as TRY
+    /// reported them; empty for the ones that failed.
+    edits: ~[~[msg::GraphEdit]],
+
+    /// settings from the rc file
+    config: config::Config,
+
+    /// pid of the current CUR, as last reported.
+    cur: libc::pid_t,
+
+    /// process group of CUR and TRY, separate from VISOR's own.
+    group: libc::pid_t,
+
+    /// pid of VISOR, which a sandboxed TRY may not signal.
+    visor: libc::pid_t,
+
+    /// older states kept for .undo and .goto
+    snapshots: snapshot::Snapshots,
+
+    /// name given to the current state with .checkpoint
+    checkpoint: Option<~str>,
+
+    /// name of the active branch; cmd, failed, cur, snapshots and
+    /// checkpoint above all belong to it.
+    branch: ~str,
+
+    /// the other branches, each with a stopped CUR.
+    branches: ~[branch::Branch],
+
+    /// true when we are interactive on a terminal, and so do job
+    /// control: TRY's group owns the terminal while it runs code.
+    tty: bool,
+
+    /// the cgroup CUR and TRY run in, if the rc file asks for one.
+    cgroup: Option<limits::Cgroup>,
+
+    /// true in a CUR or TRY that is already inside the sandbox.
+    sandboxed: bool,
+
+    /// .twice: run each snippet in a twin first, and compare.
+    twice: bool,
+
+    /// read ends of the pipes TRY's stdout and stderr come through.
+    out_fd: c_int,
+    err_fd: c_int,
+
+    /// gdb's backtrace of the last crash, for .bt.
+    backtrace: Option<~str>,
+
+    /// .postmortem: a failed TRY stops to be inspected, instead of dying.
+    post_mortem: bool,
+
+    /// pid of the failed TRY we are inspecting, if any.
+    corpse: Option<libc::pid_t>,
+
+    /// in batch mode: where each chunk's result line goes (--results).
+    results: Option<~str>,
 }
 
 impl Visor {
@@ -89,23 +214,69 @@ impl Visor {
         Visor{
             cmd: ~[],
             failed: ~[],
-            callgraph: callgraph::BothWayGraph::new(),
+            outputs: ~[],
+            defs: Defs::new(),
+            edits: ~[],
+            config: config::Config::load(),
+            cur: 0,
+            group: 0,
+            visor: 0,
+            snapshots: snapshot::Snapshots::new(),
+            checkpoint: None,
+            branch: ~"main",
+            branches: ~[],
+            tty: false,
+            cgroup: None,
+            sandboxed: false,
+            twice: false,
+            out_fd: -1,
+            err_fd: -1,
+            backtrace: None,
+            post_mortem: false,
+            corpse: None,
+            results: None,
         }
     }
 
-    pub fn start(&mut self) {
+    /// Start VISOR, CUR and TRY. With a script (a path, or "-" for
+    /// stdin) VISOR runs it in batch mode, otherwise it reads from the
+    /// terminal.
+    pub fn start(&mut self, script: Option<~str>) {
         // only TRY should get SIGINT (ctrl-c)
         util::ignore_sigint();
 
         let visor_pid = util::getpid();
         let visor_sid = util::getsid(visor_pid);
         let visor_pgrp = util::getpgrp();
+        self.visor = visor_pid;
 
         // core dumping, for now commentout: install_sigint_ctrl_c_handler();
 
         debug2!("visor called with pid:{:?}    sid:{:?}    pgrp:{:?}",
                visor_pid, visor_sid, visor_pgrp);
 
+        self.tty = script.is_none() && util::isatty(0);
+        if self.tty {
+            self.claim_terminal();
+        }
+
+        // each CUR is the child of the one before, which is killed
+        // when it is not kept. As a subreaper we adopt the orphan, so
+        // the CUR/TRY group always has a parent outside it in our
+        // session. Without one the group is orphaned: the kernel
+        // discards ctrl-z for it, and hangs up every stopped member,
+        // which takes the whole group down with the snapshots.
+        if !util::set_child_subreaper() {
+            debug2!("{:d}: VISOR: cannot become a subreaper: {:s}",
+                    visor_pid as int, os::last_os_error());
+            // then evicting a snapshot could orphan the group.
+            if self.config.snapshots > 0 {
+                io::stderr().write_line(format!("{:s}: this kernel can't keep snapshots \
+                                                 safely; snapshots = 0.", PROGRAM_NAME));
+                self.config.snapshots = 0;
+            }
+        }
+
         //
         // setup fd to communicate
         // note that os.rs has Pipe{ input and out }, and the naming of
@@ -114,6 +285,30 @@ impl Visor {
         //
         let pipe_code = os::pipe();
         let pipe_reply = os::pipe();
+        // from a sandboxed TRY to CUR: the syscall it was denied.
+        let pipe_deny = os::pipe();
+        // from TRY to CUR: the request, quit, suspend or code to run
+        // twice, TRY exited for. An exit status could be the snippet's own.
+        let pipe_exit = os::pipe();
+        // the tracing handshake. CUR tells each TRY whether it is
+        // traced; a traced TRY that succeeded says so on pipe_done and
+        // waits for CUR to have let it go.
+        let pipe_sync = os::pipe();
+        let pipe_done = os::pipe();
+        // TRY's stdout and stderr, when captured.
+        let pipe_stdout = os::pipe();
+        let pipe_stderr = os::pipe();
+        // where a crashing TRY leaves its backtrace. gdb can't be run
+        // from inside the sandbox.
+        let bt_fd = if self.config.backtrace && !self.config.sandbox {
+            crash::trace_file()
+        } else {
+            -1
+        };
+        // nor can a sandboxed TRY, once CUR, trace its own TRY.
+        let trace_effects = self.config.trace_effects && !self.config.sandbox;
+
+        self.cgroup = limits::Cgroup::create(&self.config);
 
         // I'm visor
         let pid = util::fork();
@@ -122,117 +317,49 @@ impl Visor {
             // I'm visor still.
             os::close(pipe_code.input);
             os::close(pipe_reply.out);
-
-            println(banner());
-
-            // READ LOOP: read code from stdin, send it on pipe_code
-            loop {
-                // cleanup zombies from when TRY succeeded and killed CUR
-                let mut zombstatus :i32 = 0;
-                util::waitpid_async(-1, &mut zombstatus);
-
-                print(prompt());
-                let code = io::stdin().read_line();
-
-                let trimmed_code = code.trim();
-                // match meta commands: keep these distinguished by the
-                // first character for ease of typing and parsing.
-                match trimmed_code {
-                    "" if io::stdin().eof() => {
-                        // ctrl-d should exit so we can send files on stdin eventually.
-                        debug2!("{:d}: VISOR: I see EOF", util::getpid() as int);
-                        println("");
-
-                        // send EOF on pipe_code to TRY, so it knows to shut itself down.
-                        os::close(pipe_code.out);
-                        os::close(pipe_reply.input);
-
-                        // that's not working yet, so cleanup for sure with allquit().
-                        util::process_group_exit();
-                    },
-                    "" => continue,
-                    ".q" => util::process_group_exit(),
-                    ".?" => {
-                        println(help());
-                        continue;
-                    },
-                    ".c" => {
-                        // correct history only... failed commands commented out.
-                        let mut i = 0;
-                        for c in self.cmd.iter() {
-                            if (self.failed[i]) { print!("{:s}", "//not: ") }
-                            println!("{:s}", *c);
-                            i = i + 1;
-                        }
-                        continue;
-                    },
-                    ".h" => {
-                        for c in self.cmd.iter() {
-                            println!("{:s}", *c);
-                        }
-                        continue;
-                    },
-                    ".s" => {
-                        println("TODO: implement .s <file> sourcing.");
-                        continue;
-                    },
-                    ".." => {
-                        println("TODO: implement system(cmd) shell outs.");
-                        continue;
-                    },
-                    _ => {
-                        // not a meta command: add to history
-                        self.cmd.push(code.clone());
-                    },
-                }
-
-                debug2!("visor is: {:?}", self);
-
-                let mut buffer = ~[0u8, ..CODEBUF_SIZE];
-                vec::bytes::copy_memory(buffer, code.as_bytes(), code.len());
-                buffer.truncate(code.len());
-                //debug2!("buffer is '{:?}' after copy from '{:s}'", buffer, code);
-
-                // send code over to TRY
-                do buffer.as_mut_buf |ptr, len| {
-                    util::write(pipe_code.out, ptr as *libc::c_void, len as u64);
-                }
-
-                // wait for reply
-                debug2!("{:d}: I am VISOR: waiting for more, success, or failed",
-                util::getpid() as int);
-                // wait for "more" (from TRY) or "done" (from TRY) or "failed" (from CUR)
-                let mut replybuf = ~[0u8, ..8];
-
-                let bytesread = do replybuf.as_mut_buf |ptr, len| {
-                    util::read(pipe_reply.input, ptr as *mut libc::c_void, len as u64)
-                };
-
-                if bytesread < 0 {
-                    fail2!("{:d}: I am VISOR: visor failed to read from code_pipe: {:s}",
-                          util::getpid() as int,
-                          os::last_os_error());
-                }
-
-                let replystr = do replybuf.as_mut_buf |ptr, _| {
-                    util::copy_buf_to_string(ptr, bytesread as uint)
-                };
-
-                match replystr {
-                    ~"failed"  => self.failed.push(true),
-                    ~"success" => self.failed.push(false),
-                    _ => fail2!("VISOR doesn't recongize reply for CUR/TRY: {:s}", replystr),
-                }
-
-                debug2!("{:d}: I am VISOR: I got an '{:d}' byte message back: '{:s}'",
-                       util::getpid() as int,
-                       bytesread as int, replystr);
-
+            os::close(pipe_deny.input);
+            os::close(pipe_deny.out);
+            os::close(pipe_exit.input);
+            os::close(pipe_exit.out);
+            os::close(pipe_sync.input);
+            os::close(pipe_sync.out);
+            os::close(pipe_done.input);
+            os::close(pipe_done.out);
+            os::close(pipe_stdout.out);
+            os::close(pipe_stderr.out);
+            if bt_fd >= 0 {
+                os::close(bt_fd);
+            }
+            self.out_fd = pipe_stdout.input;
+            self.err_fd = pipe_stderr.input;
+            self.cur = pid;
+
+            // CUR and TRY get a process group of their own, so signals
+            // meant for them (and for us) don't leak to the other side
+            // or to the shell. CUR does the same; whichever runs first
+            // wins the race harmlessly.
+            util::setpgid(pid, pid);
+            self.group = pid;
+
+            match script {
+                Some(path) => self.run_script(path.as_slice(), pipe_code.out, pipe_reply.input),
+                None => self.interactive(pipe_code.out, pipe_reply.input),
             }
         } else {
             // I'm CUR after first fork, setup pipes on my end:
             os::close(pipe_code.out);
             os::close(pipe_reply.input);
+            os::close(pipe_stdout.input);
+            os::close(pipe_stderr.input);
+
+            util::setpgid(0, 0);
+            util::default_signal(signum::SIGTTOU);
+
+            // join the cgroup before forking, so every TRY is in it too.
+            match self.cgroup {
+                Some(ref cg) => { cg.add(util::getpid()); },
+                None => (),
+            }
 
             // TODO: needed? util::ll::rust_unset_sigprocmask();
 
@@ -245,96 +372,1237 @@ impl Visor {
         // TRY tries out the new code. If it finishes without fail!()-ing,
         //   then TRY replaces CUR.
 
+        // in .twice mode: code for the next TRY to run rather than read
+        // from VISOR, and whether that TRY is the twin.
+        let mut queued: Option<(~str, msg::Flags, bool)> = None;
+
         // steady-state: I'm CUR
         loop {
+            // ctrl-c and ctrl-z are for TRY; CUR stays put.
             util::ignore_sigint();
+            util::ignore_signal(signum::SIGTSTP);
 
             debug2!("{:d}: I am CUR: top of steady-state loop. About to fork a new TRY. parent: {:d}",
                    util::getpid() as int,
                    util::getppid() as int);
 
+            let cur_pid = util::getpid();
+            let events_before = self.cgroup.map(|cg| cg.events());
+            // an earlier TRY may have died mid-handshake.
+            util::drain(pipe_sync.input);
+            util::drain(pipe_done.input);
+            let given = queued.take();
             let pid = util::fork();
             if pid == 0 {
                 // I am TRY, child of CUR. I try new code out and succeed 
-                // (and thence kill CUR and become CUR), or die.
+                // (and thence become CUR), or die.
                 // TODO: needed? where? util::ll::rust_unset_sigprocmask();
                 install_sigint_ctrl_c_handler();
+                util::default_signal(signum::SIGTSTP);
+
+                // if VISOR kills CUR (on .undo, .goto or shutdown) while
+                // we are waiting for code, we go too.
+                util::set_parent_death_signal(signum::SIGKILL);
+                if util::getppid() != cur_pid {
+                    util::exit(0);
+                }
+                // not a syscall before CUR has tried to trace us.
+                let traced = match util::read_byte(pipe_sync.input) {
+                    Some(b) => b == 'T' as u8,
+                    None => util::exit(0),
+                };
 
                 debug2!("{:d}: I am TRY: about to request code line.",
                        util::getpid() as int);
 
-                let mut buffer = ~[0u8, ..CODEBUF_SIZE];
-                let mut bytes_read: i64;
-                loop {
-                    bytes_read = do buffer.as_mut_buf |ptr, len| {
-                        util::read(pipe_code.input, ptr as *mut libc::c_void, len as u64)
-                    };
+                let (code, flags, is_twin) = match given {
+                    Some(q) => q,
+                    None => match msg::recv(pipe_code.input).and_then(|m| msg::Request::decode(m)) {
+                        Some(msg::Code(c, flags)) => {
+                            if flags.twice {
+                                // both runs are to be TRYs of CUR's, from its
+                                // state: hand the code back for CUR to fork them.
+                                msg::send(pipe_exit.out, msg::Code(c, flags).encode());
+                                util::exit(0);
+                            }
+                            (c, flags, false)
+                        },
+                        Some(msg::Branch) => {
+                            let keeper = util::fork();
+                            if keeper == 0 {
+                                // I am the copy kept for the new branch. Sleep
+                                // until VISOR switches to it, then carry on as
+                                // its CUR.
+                                util::kill(util::getpid(), signum::SIGSTOP);
+                                continue;
+                            }
+                            reply("TRY", msg::Branched(keeper), pipe_reply.out);
+                            // CUR sees a failed TRY, and forks a fresh one.
+                            util::exit(0)
+                        },
+                        Some(msg::Suspend) => {
+                            msg::send(pipe_exit.out, msg::Suspend.encode());
+                            util::exit(0)
+                        },
+                        Some(msg::Quit) | None => {
+                            // VISOR asked us to quit, or closed its end. Leave
+                            // start() normally, so destructors run and output
+                            // is flushed; CUR learns it was a clean quit and
+                            // not a failure over pipe_exit.
+                            debug2!("{:d}: TRY: quitting.", util::getpid() as int);
+                            msg::send(pipe_exit.out, msg::Quit.encode());
+                            return;
+                        },
+                    },
+                };
 
-                    if bytes_read < 0 {
-                        debug2!("read on pipe_code.out failed with errno: {:?} '{:?}'", 
-                               os::errno(), os::last_os_error());
-                        break;
+                debug2!("{:d}: TRY: I see code to run: '{:s}'", util::getpid() as int, code);
+                // what an earlier TRY, now our CUR, told VISOR is done with.
+                self.defs.pending.clear();
+
+                if self.config.sandbox && !self.sandboxed {
+                    match sandbox::enter(self.config.sandbox_allow, pipe_deny.out, self.visor) {
+                        Ok(()) => self.sandboxed = true,
+                        Err(e) => {
+                            // better not to run the code at all.
+                            io::stderr().write_line(
+                                format!("{:s}: cannot enter the sandbox: {:s}", PROGRAM_NAME, e));
+                            util::exit(1);
+                        },
                     }
-                    if bytes_read > 0 { break; }
                 }
-
-                debug2!("bytes_read is {:d}", bytes_read as int);
-                buffer.truncate(bytes_read as uint);
-                let code = do buffer.as_mut_buf |ptr, _| {
-                    util::copy_buf_to_string(ptr, bytes_read as uint)
+                let saved = limits::apply(&self.config);
+                crash::install(self.config.gdb, bt_fd);
+                let saved_output = if self.config.capture || flags.twice {
+                    Some(capture::redirect(pipe_stdout.out, pipe_stderr.out))
+                } else {
+                    None
                 };
-
-                debug2!("{:d}: TRY: I see code to run: '{:s}'", util::getpid() as int, code);
-
-                let trimmed_code = code.trim_left();
-                if trimmed_code.char_len() > 2 && trimmed_code.slice_to(2) == ".g" {
-                    self.callgraph_exec(trimmed_code.slice_from(2));
+                // the twin's failure is for comparing, not inspecting.
+                if flags.post_mortem && !is_twin {
+                    // a failure, of the code or of a check it leads
+                    // to, leaves us stopped, for VISOR to inspect. The
+                    // task gets our defs, and gives them back changed
+                    // if it succeeds.
+                    let defs = Cell::new(::std::util::replace(&mut self.defs, Defs::new()));
+                    let code = code.to_owned();
+                    let ran = do postmortem::try_run {
+                        let mut defs = defs.take();
+                        defs.run_code(code);
+                        defs
+                    };
+                    match ran {
+                        Some(defs) => self.defs = defs,
+                        None => postmortem::linger(pipe_code.input, pipe_reply.out),
+                    }
                 } else {
-                    /*
-                     *  here is where call to do the majority of the
-                     *  actual work: compile and run the code.
-                     */
-                    compile::compile_and_run(code);
+                    self.defs.run_code(code);
                 }
 
-                // we become the new CUR, so ignore ctrl-c again.
+                // we become the new CUR: limits were for running code,
+                // and ctrl-c and ctrl-z are ignored again.
+                limits::restore(saved);
+                match saved_output {
+                    Some(o) => capture::restore(o),
+                    None => (),
+                }
+                if is_twin {
+                    // CUR tells VISOR how we did, and forks the TRY that
+                    // runs the code for real.
+                    util::exit(0);
+                }
                 util::ignore_sigint();
-                debug2!("{:d}: TRY succeeded in running the code, killing old CUR \
-                        and I will become the new CUR.",
+                util::ignore_signal(signum::SIGTSTP);
+                // from here on we outlive our parent: VISOR either kills
+                // the old CUR or keeps it stopped as a snapshot.
+                util::set_parent_death_signal(0);
+                debug2!("{:d}: TRY succeeded in running the code, I will become the new CUR.",
                         util::getpid() as int);
-                let ppid = util::getppid();
-                util::kill(ppid, libc::SIGTERM);
-
-                // we are already a part of the visor's group, 
-                // just we have init (pid 1) as a parent now.
-                debug2!("{:d}: TRY: I'm channeling Odysseus. I just killed ppid {:d} with SIGTERM.",
-                       util::getpid() as int, ppid as int);
+                if traced {
+                    util::write_byte(pipe_done.out, 'D' as u8);
+                    util::read_byte(pipe_sync.input);
+                }
 
-                pipe32reply("TRY", "success", pipe_reply.out);
+                let edits = ::std::util::replace(&mut self.defs.pending, ~[]);
+                reply("TRY", msg::Success(util::getpid(), edits), pipe_reply.out);
             } else {
-                // I am CUR. I wait for TRY to finish. If TRY succeeds I never
-                // wake up. If TRY fails, I goto the
-                // top of the steady-state loop and try again
-                let mut status = 0 as c_int;
-                util::waitpid(pid, &mut status);
+                // I am CUR. I wait for TRY to finish. If TRY succeeds, VISOR
+                // kills me, or stops me and keeps me as a snapshot. If TRY
+                // fails, or I am a snapshot being restored (VISOR killed my
+                // child), I goto the top of the steady-state loop and try again
+                let tracer = if trace_effects {
+                    trace::Tracer::attach(pid, ~[0, 1, 2, pipe_reply.out, pipe_deny.out,
+                                                 pipe_exit.out, pipe_stdout.out, pipe_stderr.out],
+                                          pipe_done.out, bt_fd >= 0)
+                } else {
+                    None
+                };
+                let word = if tracer.is_some() { 'T' } else { 'U' };
+                util::write_byte(pipe_sync.out, word as u8);
+                // TRY is let go: take its word off the pipe and answer.
+                let on_release = || {
+                    util::read_byte(pipe_done.input);
+                    util::write_byte(pipe_sync.out, 'A' as u8);
+                };
+                // ctrl-z stopped TRY: let VISOR suspend the job.
+                let on_stop = || reply("CUR", msg::Stopped(util::getpid()), pipe_reply.out);
+                let (status, effects, alloc_failed) = match tracer {
+                    Some(t) => t.wait(on_stop, on_release),
+                    None => (trace::wait_untraced(pid, on_stop), ~[], false),
+                };
+
+                // read whatever TRY said, so it can't be taken for the
+                // next TRY's word.
+                let obeyed = if util::poll_readable(pipe_exit.input, 0) {
+                    msg::recv(pipe_exit.input).and_then(|m| msg::Request::decode(m))
+                } else {
+                    None
+                };
+                match obeyed {
+                    Some(msg::Quit) if util::exit_status(status) == Some(0) => {
+                        // TRY quit as VISOR asked; we follow it out the same way.
+                        debug2!("{:d}: CUR saw TRY quit, quitting too.", util::getpid() as int);
+                        reply("CUR", msg::Quitting(util::getpid()), pipe_reply.out);
+                        return;
+                    },
+                    Some(msg::Suspend) if util::exit_status(status) == Some(0) => {
+                        // VISOR is switching to another branch; sleep until
+                        // it switches back to ours.
+                        reply("CUR", msg::Suspended(util::getpid()), pipe_reply.out);
+                        util::kill(util::getpid(), signum::SIGSTOP);
+                        continue;
+                    },
+                    Some(msg::Code(c, flags)) => if util::exit_status(status) == Some(0) {
+                        // .twice: run the code in two TRYs of ours, the
+                        // twin first, each starting from our state.
+                        queued = Some((c, flags, true));
+                        continue;
+                    },
+                    _ => (),
+                }
+
+                match given {
+                    Some((c, flags, true)) => {
+                        // the twin never becomes CUR, and what it left for
+                        // the backtrace and deny pipes isn't the next TRY's.
+                        match util::term_signal(status) {
+                            Some(sig) if crash::is_crash(sig) => { crash::take(bt_fd); },
+                            _ => (),
+                        }
+                        if util::exit_status(status) == Some(sandbox::DENIED_STATUS) {
+                            sandbox::denied_syscall(pipe_deny.input);
+                        }
+                        let ok = util::exit_status(status) == Some(0);
+                        reply("CUR", msg::Twin(pid, ok, effects), pipe_reply.out);
+                        queued = Some((c, flags, false));
+                        continue;
+                    },
+                    _ => (),
+                }
+
                 debug2!("{:d}: CUR saw TRY process exit, must have failed. {:s}",
                        util::getpid() as int,
                        "Going to top of loop to spawn a new try.");
 
-                // pipe "failed" to VISOR:
-                pipe32reply("CUR", "failed", pipe_reply.out);
+                match util::term_signal(status) {
+                    Some(sig) if crash::is_crash(sig) => match crash::take(bt_fd) {
+                        Some(text) => reply("CUR", msg::Backtrace(util::getpid(), text),
+                                            pipe_reply.out),
+                        None => (),
+                    },
+                    _ => (),
+                }
+
+                // pipe "failed" to VISOR, with the reason:
+                let denied = if util::exit_status(status) == Some(sandbox::DENIED_STATUS) {
+                    sandbox::denied_syscall(pipe_deny.input)
+                } else {
+                    None
+                };
+                let why = match denied {
+                    Some(syscall) => msg::Denied(syscall),
+                    None => limits::classify(status, &self.cgroup, &events_before, alloc_failed),
+                };
+                reply("CUR", msg::Failed(util::getpid(), why, effects), pipe_reply.out);
             }
         }
 
 
     } // end start()
 
+    // VISOR's interactive READ LOOP: read code from the terminal, send
+    // it on code_fd, and record the outcome that comes back on reply_fd.
+    fn interactive(&mut self, code_fd: c_int, reply_fd: c_int) -> ! {
+        println(banner());
+
+        // line editing and ctrl-r history search need a terminal.
+        let mut editor = if util::isatty(0) {
+            let mut ed = editline::Editor::new(PROGRAM_NAME, prompt(), &self.cmd, &self.failed);
+            // escapes would end up in captured output, so only
+            // highlight when we are drawing on a terminal.
+            if !ed.set_highlight(self.config.highlight && util::isatty(1)) {
+                debug2!("{:d}: VISOR: this libedit can't highlight.", util::getpid() as int);
+            }
+            Some(ed)
+        } else {
+            None
+        };
+
+        loop {
+            // cleanup zombies: the old CURs we killed, ours by adoption.
+            util::reap_exited();
+
+            let p = if self.corpse.is_some() { post_mortem_prompt() } else { prompt() };
+            let code = match read_line(&mut editor, p) {
+                Some(line) => line,
+                None => {
+                    // ctrl-d exits
+                    debug2!("{:d}: VISOR: I see EOF", util::getpid() as int);
+                    println("");
+                    self.shutdown(code_fd, reply_fd, EXIT_OK)
+                },
+            };
+
+            let trimmed_code = code.trim();
+            let (word, arg) = meta_split(trimmed_code);
+            if self.corpse.is_some() {
+                // at the post-mortem> prompt: expressions go to the
+                // failed state; most meta commands must wait.
+                match word {
+                    "" => (),
+                    ".q" => self.shutdown(code_fd, reply_fd, EXIT_OK),
+                    ".?" => println(help()),
+                    ".abandon" => self.abandon(reply_fd),
+                    w if w.starts_with(".") => println("first .abandon the post-mortem."),
+                    _ => self.inspect(code.as_slice(), code_fd, reply_fd),
+                }
+                continue;
+            }
+            // match meta commands: keep these distinguished by the
+            // first character for ease of typing and parsing.
+            match word {
+                "" => continue,
+                ".q" => self.shutdown(code_fd, reply_fd, EXIT_OK),
+                ".?" => {
+                    println(help());
+                    continue;
+                },
+                ".c" => {
+                    // correct history only... failed commands commented out.
+                    let mut i = 0;
+                    for c in self.cmd.iter() {
+                        if (self.failed[i]) { print!("{:s}", "//not: ") }
+                        println!("{:s}", *c);
+                        i = i + 1;
+                    }
+                    continue;
+                },
+                ".h" => {
+                    if arg == "-v" {
+                        for (i, c) in self.cmd.iter().enumerate() {
+                            println!("{:u}: {:s}", i + 1, *c);
+                            self.outputs[i].replay();
+                        }
+                    } else {
+                        for c in self.cmd.iter() {
+                            println!("{:s}", *c);
+                        }
+                    }
+                    continue;
+                },
+                ".g" if arg.len() == 0 => {
+                    self.show_graph();
+                    continue;
+                },
+                ".g?" => {
+                    self.graph_query(arg);
+                    continue;
+                },
+                ".gdot" | ".gjson" => {
+                    self.save_graph(word, arg);
+                    continue;
+                },
+                ".export" => {
+                    self.export(arg);
+                    continue;
+                },
+                ".show" => {
+                    match from_str::<uint>(arg) {
+                        Some(n) if n >= 1 && n <= self.outputs.len() => {
+                            self.outputs[n - 1].replay();
+                        },
+                        _ => println!("usage: .show N, where N is from 1 to {:u}; .h -v numbers them.",
+                                      self.outputs.len()),
+                    }
+                    continue;
+                },
+                ".r" => {
+                    match editor {
+                        Some(ref mut ed) => {
+                            if ed.toggle_correct_only() {
+                                println("ctrl-r now searches correct history only.");
+                            } else {
+                                println("ctrl-r now searches all history.");
+                            }
+                        },
+                        None => println("no line editor: stdin is not a terminal."),
+                    }
+                    continue;
+                },
+                ".bt" => {
+                    match self.backtrace {
+                        Some(ref text) => println(*text),
+                        None => println("no crash yet, so no backtrace."),
+                    }
+                    continue;
+                },
+                ".twice" => {
+                    self.twice = !self.twice;
+                    if self.twice {
+                        println("each snippet now runs twice, and the runs are compared.");
+                    } else {
+                        println("snippets run once.");
+                    }
+                    continue;
+                },
+                ".postmortem" => {
+                    self.post_mortem = !self.post_mortem;
+                    if self.post_mortem {
+                        println("a failed snippet now stops for inspection at a post-mortem> prompt.");
+                    } else {
+                        println("failed snippets are rolled back straight away.");
+                    }
+                    continue;
+                },
+                ".undo" => {
+                    self.undo(reply_fd);
+                    continue;
+                },
+                ".checkpoint" => {
+                    self.checkpoint(arg);
+                    continue;
+                },
+                ".goto" => {
+                    self.goto(arg, reply_fd);
+                    continue;
+                },
+                ".branch" => {
+                    self.branch(arg, code_fd, reply_fd);
+                    continue;
+                },
+                ".branches" => {
+                    self.list_branches();
+                    continue;
+                },
+                ".switch" => {
+                    self.switch(arg, code_fd, reply_fd);
+                    continue;
+                },
+                ".s" => {
+                    println("TODO: implement .s <file> sourcing.");
+                    continue;
+                },
+                ".." => {
+                    println("TODO: implement system(cmd) shell outs.");
+                    continue;
+                },
+                _ => (),
+            }
+
+            self.transact(code.as_slice(), code_fd, reply_fd);
+        }
+    }
+
+    // VISOR in batch mode: no banner and no prompt. Each chunk of the
+    // script is one transaction, reported in machine-readable form to
+    // the --results file, if there is one; we exit non-zero if any
+    // chunk failed. A chunk that is just .q ends the script early.
+    fn run_script(&mut self, path: &str, code_fd: c_int, reply_fd: c_int) -> ! {
+        let text = match script::read(path) {
+            Ok(t) => t,
+            Err(e) => {
+                io::stderr().write_line(format!("{:s}: {:s}", PROGRAM_NAME, e));
+                self.shutdown(code_fd, reply_fd, EXIT_NO_SCRIPT)
+            },
+        };
+        let results = match self.results {
+            None => None,
+            Some(ref p) => match io::file_writer(&path::Path(*p), [io::Create, io::Truncate]) {
+                Ok(w) => Some(w),
+                Err(e) => {
+                    io::stderr().write_line(format!("{:s}: --results: {:s}", PROGRAM_NAME, e));
+                    self.shutdown(code_fd, reply_fd, EXIT_NO_SCRIPT)
+                },
+            },
+        };
+
+        let mut any_failed = false;
+        for chunk in script::chunks(text.as_slice()).iter() {
+            util::reap_exited();
+            if chunk.code.trim() == ".q" {
+                break;
+            }
+            let failed = self.transact(chunk.code.as_slice(), code_fd, reply_fd);
+            match results {
+                Some(w) => {
+                    w.write_line(script::report(chunk, failed));
+                    w.flush();
+                },
+                None => (),
+            }
+            any_failed = any_failed || failed;
+        }
+        self.shutdown(code_fd, reply_fd, if any_failed { EXIT_FAILED } else { EXIT_OK })
+    }
+
+    // TRY, with this pid, succeeded and is the new CUR. The old CUR is
+    // kept, stopped, as a snapshot if snapshots are on or if it was
+    // named with .checkpoint; otherwise it has served its purpose.
+    fn commit(&mut self, pid: libc::pid_t) {
+        let old = self.cur;
+        self.cur = pid;
+        let name = ::std::util::replace(&mut self.checkpoint, None);
+        if self.config.snapshots == 0 && name.is_none() {
+            util::kill(old, signum::SIGTERM);
+            return;
+        }
+        util::kill(old, signum::SIGSTOP);
+        let snap = snapshot::Snapshot { pid: old, ncmd: self.cmd.len() - 1, name: name };
+        for &evicted in self.snapshots.push(snap, self.config.snapshots).iter() {
+            util::kill(evicted, signum::SIGKILL);
+        }
+    }
+
+    // .undo: back to the state before the last successful command. The
+    // newest snapshot is that state unless only checkpoints are kept,
+    // in which case it may be many commands back; .goto is for those.
+    fn undo(&mut self, reply_fd: c_int) {
+        let n = self.snapshots.len();
+        let last_ok = range(0, self.failed.len()).invert().find(|&j| !self.failed[j]);
+        let before_last = match (self.snapshots.iter().last(), last_ok) {
+            (Some(s), Some(j)) if s.ncmd == j => true,
+            _ => false,
+        };
+        if !before_last {
+            println("nothing to undo: no snapshot kept of the state before the last command. \
+                     Set snapshots = N in ~/.rustxirc.");
+            match self.snapshots.iter().last() {
+                Some(&snapshot::Snapshot { name: Some(ref name), _ }) => {
+                    println!(".goto {:s} returns to that checkpoint.", *name);
+                },
+                _ => (),
+            }
+            return;
+        }
+        self.restore(n - 1, reply_fd);
+    }
+
+    // .checkpoint name: name the current state. With no name, list them.
+    fn checkpoint(&mut self, name: &str) {
+        if name.len() == 0 {
+            for s in self.snapshots.iter() {
+                match s.name {
+                    Some(ref n) => println!("{:s}  (after {:u} commands)", *n, s.ncmd),
+                    None => println!("-  (after {:u} commands)", s.ncmd),
+                }
+            }
+            match self.checkpoint {
+                Some(ref n) => println!("{:s}  (current)", *n),
+                None => (),
+            }
+            return;
+        }
+        if self.snapshots.find(name).is_some() {
+            println!("there is already a checkpoint named '{:s}'.", name);
+            return;
+        }
+        match self.checkpoint {
+            Some(ref old) if old.as_slice() != name => {
+                println!("the current state was named '{:s}'; now it is '{:s}'.", *old, name);
+            },
+            _ => (),
+        }
+        self.checkpoint = Some(name.to_owned());
+    }
+
+    fn goto(&mut self, name: &str, reply_fd: c_int) {
+        match self.checkpoint {
+            Some(ref n) if n.as_slice() == name => {
+                println!("already at '{:s}'.", name);
+                return;
+            },
+            _ => (),
+        }
+        match self.snapshots.find(name) {
+            Some(i) => self.restore(i, reply_fd),
+            None => println!("no checkpoint named '{:s}'.", name),
+        }
+    }
+
+    // make snapshot i CUR again: kill everything forked after it (the
+    // newer snapshots, and CUR, which takes its idle TRY along), then
+    // wake it. It sees its child die and reports failed, just as after
+    // a failed TRY, and forks a fresh TRY.
+    fn restore(&mut self, i: uint, reply_fd: c_int) {
+        match self.checkpoint {
+            Some(ref name) => println!("the checkpoint '{:s}' named the state we are leaving; \
+                                        it is gone.", *name),
+            None => (),
+        }
+        let mut tail = self.snapshots.split_off(i);
+        let target = tail.shift();
+        for s in tail.iter() {
+            match s.name {
+                Some(ref name) => println!("the checkpoint '{:s}' is gone too.", *name),
+                None => (),
+            }
+        }
+        for s in tail.iter() {
+            util::kill(s.pid, signum::SIGKILL);
+        }
+        util::kill(self.cur, signum::SIGKILL);
+        util::kill(target.pid, signum::SIGCONT);
+
+        loop {
+            match self.recv_reply(reply_fd) {
+                // the child was stopped before it died; target was
+                // stopped too, so it hears about that first.
+                Some(msg::Stopped(_)) => continue,
+                Some(msg::Failed(pid, _, _)) if pid == target.pid => break,
+                r => fail2!("VISOR: unexpected reply restoring snapshot {:d}: {:?}",
+                            target.pid as int, r),
+            }
+        }
+        self.cur = target.pid;
+        self.checkpoint = target.name;
+
+        // the commands from ncmd on have been rolled back. They leave
+        // the history: kept, they would read as failed.
+        for j in range(target.ncmd, self.failed.len()) {
+            if !self.failed[j] {
+                println!("undone: {:s}", self.cmd[j]);
+            }
+        }
+        self.cmd.truncate(target.ncmd);
+        self.failed.truncate(target.ncmd);
+        self.outputs.truncate(target.ncmd);
+        self.edits.truncate(target.ncmd);
+        self.rebuild_graph();
+    }
+
+    // VISOR: the call graph as the commands that stand left it.
+    fn rebuild_graph(&mut self) {
+        self.defs.callgraph = callgraph::BothWayGraph::new();
+        for (es, &f) in self.edits.iter().zip(self.failed.iter()) {
+            if !f {
+                for e in es.iter() {
+                    callgraph::apply(&mut self.defs.callgraph, e);
+                }
+            }
+        }
+    }
+
+    // .branch name: TRY forks a stopped copy of the current state, which
+    // becomes the CUR of the new branch. We stay in the active branch.
+    fn branch(&mut self, name: &str, code_fd: c_int, reply_fd: c_int) {
+        if name.len() == 0 {
+            println("usage: .branch name");
+            return;
+        }
+        if name == self.branch || branch::find(self.branches, name).is_some() {
+            println!("there is already a branch named '{:s}'.", name);
+            return;
+        }
+        if !msg::send(code_fd, msg::Branch.encode()) {
+            fail2!("{:d}: I am VISOR: failed to send branch request to TRY: {:s}",
+                   util::getpid() as int, os::last_os_error());
+        }
+        let keeper = match self.recv_reply(reply_fd) {
+            Some(msg::Branched(pid)) => pid,
+            r => fail2!("VISOR: unexpected reply to branch: {:?}", r),
+        };
+        // and CUR reports the exit of that TRY as a failure.
+        match self.recv_reply(reply_fd) {
+            Some(msg::Failed(pid, _, _)) => self.cur = pid,
+            r => fail2!("VISOR: unexpected reply after branch: {:?}", r),
+        }
+        self.branches.push(branch::Branch {
+            name: name.to_owned(),
+            cur: keeper,
+            cmd: self.cmd.clone(),
+            failed: self.failed.clone(),
+            outputs: self.outputs.clone(),
+            edits: self.edits.clone(),
+            snapshots: snapshot::Snapshots::new(),
+            checkpoint: None,
+        });
+        println!("branch '{:s}' created; still on '{:s}'.", name, self.branch);
+    }
+
+    fn list_branches(&self) {
+        println!("* {:s}  ({:u} commands ok)", self.branch,
+                 self.failed.iter().count(|&f| !f));
+        print_history(self.cmd, self.failed, "    ");
+        for b in self.branches.iter() {
+            println!("  {:s}  ({:u} commands ok)", b.name, b.ok_count());
+            print_history(b.cmd, b.failed, "    ");
+        }
+    }
+
+    // .g? query: ask VISOR's call graph, which is that of CUR.
+    fn graph_query(&self, query: &str) {
+        let (q, func) = meta_split(query);
+        let g = &self.defs.callgraph;
+        match q {
+            "callers" | "callees" | "dependents" | "deps" => {
+                if func.len() == 0 {
+                    println!("usage: .g? {:s} f", q);
+                } else if !g.contains(&[func]) {
+                    println!("no fn named '{:s}' in the call graph.", func);
+                } else {
+                    print_fns(match q {
+                        "callers" => g.callers_of(func),
+                        "callees" => g.callees_of(func),
+                        "dependents" => g.dependents_of(func),
+                        _ => g.deps_of(func),
+                    });
+                }
+            },
+            "cycles" => {
+                let cycles = g.cycles();
+                if cycles.len() == 0 {
+                    println("no cycles.");
+                }
+                for c in cycles.move_iter() {
+                    print_fns(c);
+                }
+            },
+            "unused" => print_fns(g.unused()),
+            "broken" => print_fns(g.broken_fns()),
+            "unresolved" => print_fns(g.unresolved_fns()),
+            _ => println("usage: .g? callers|callees|dependents|deps f, \
+                          .g? cycles, .g? unused, .g? broken or .g? unresolved"),
+        }
+    }
+
+    // .g: each fn and what it calls; a deleted or undefined callee is
+    // marked, and so is a fn left broken by a deleted one.
+    fn show_graph(&self) {
+        let g = &self.defs.callgraph;
+        let fns = g.live();
+        if fns.len() == 0 {
+            println("the call graph is empty.");
+        }
+        let broken = g.broken_fns();
+        for f in fns.iter() {
+            let calls: ~[~str] = g.calls_of(*f).move_iter().map(|(c, status)| {
+                match status {
+                    callgraph::Live => c,
+                    callgraph::Deleted => format!("{:s} (deleted)", c),
+                    callgraph::Unresolved => format!("{:s} (not defined yet)", c),
+                }
+            }).collect();
+            let mark = if broken.contains(f) { "  -- broken: redefine it, or what it calls" } else { "" };
+            let kind = g.kind_of(*f).unwrap_or_default(callgraph::Fn);
+            println!("{:s} {:s}: {:s}{:s}", kind.name(), *f, calls.connect(", "), mark);
+        }
+    }
+
+    // .gdot file, .gjson file: write the call graph out.
+    fn save_graph(&self, cmd: &str, path: &str) {
+        if path.len() == 0 {
+            println!("usage: {:s} file", cmd);
+            return;
+        }
+        let g = &self.defs.callgraph;
+        let text = if cmd == ".gdot" { g.to_dot() } else { g.to_json() };
+        match io::file_writer(&path::Path(path), [io::Create, io::Truncate]) {
+            Ok(w) => {
+                w.write_str(text);
+                println!("wrote the call graph, {:u} fns, to {:s}", self.defs.callgraph.live().len(), path);
+            },
+            Err(e) => println!("cannot write the call graph: {:s}", e),
+        }
+    }
+
+    // .export [-f] file.rs: what .c shows, minus the failures and the
+    // .g graph commands, as a program.
+    fn export(&self, arg: &str) {
+        let (force, path) = if arg.starts_with("-f ") {
+            (true, arg.slice_from(3).trim())
+        } else {
+            (false, arg)
+        };
+        if path.len() == 0 || path == "-f" {
+            println("usage: .export [-f] file.rs");
+            return;
+        }
+        let mut snippets = ~[];
+        for (c, &f) in self.cmd.iter().zip(self.failed.iter()) {
+            if !f && !c.trim_left().starts_with(".g") {
+                snippets.push(c.clone());
+            }
+        }
+        match export::write(path, snippets, force) {
+            Ok(done) => for d in done.iter() { println(*d); },
+            Err(e) => println!("cannot export: {:s}", e),
+        }
+    }
+
+    // .switch name: park the active branch, with its CUR stopped, and
+    // make another one active.
+    fn switch(&mut self, name: &str, code_fd: c_int, reply_fd: c_int) {
+        if name == self.branch {
+            println!("already on '{:s}'.", name);
+            return;
+        }
+        let i = match branch::find(self.branches, name) {
+            Some(i) => i,
+            None => {
+                println!("no branch named '{:s}'.", name);
+                return;
+            },
+        };
+
+        // TRY exits, and CUR stops itself; it will fork a new TRY when
+        // we continue it.
+        if !msg::send(code_fd, msg::Suspend.encode()) {
+            fail2!("{:d}: I am VISOR: failed to send suspend request to TRY: {:s}",
+                   util::getpid() as int, os::last_os_error());
+        }
+        match self.recv_reply(reply_fd) {
+            Some(msg::Suspended(pid)) if pid == self.cur => (),
+            r => fail2!("VISOR: unexpected reply to suspend: {:?}", r),
+        }
+
+        let next = self.branches.remove(i);
+        let parked = branch::Branch {
+            name: ::std::util::replace(&mut self.branch, next.name),
+            cur: self.cur,
+            cmd: ::std::util::replace(&mut self.cmd, next.cmd),
+            failed: ::std::util::replace(&mut self.failed, next.failed),
+            outputs: ::std::util::replace(&mut self.outputs, next.outputs),
+            edits: ::std::util::replace(&mut self.edits, next.edits),
+            snapshots: ::std::util::replace(&mut self.snapshots, next.snapshots),
+            checkpoint: ::std::util::replace(&mut self.checkpoint, next.checkpoint),
+        };
+        self.branches.push(parked);
+        self.rebuild_graph();
+        self.cur = next.cur;
+        util::kill(self.cur, signum::SIGCONT);
+        println!("switched to '{:s}'.", self.branch);
+    }
+
+    // like a job-control shell at startup: wait until we are in the
+    // foreground, then lead our own process group and own the terminal.
+    fn claim_terminal(&mut self) {
+        loop {
+            let pgrp = util::getpgrp();
+            if util::tcgetpgrp(0) == pgrp {
+                break;
+            }
+            util::kill(-pgrp, signum::SIGTTIN);
+        }
+        // we hand the terminal to CUR/TRY's group and take it back; the
+        // taking back happens while we are in the background.
+        util::ignore_signal(signum::SIGTTOU);
+
+        let pid = util::getpid();
+        if util::getpgrp() != pid && util::setpgid(pid, pid) < 0 {
+            debug2!("{:d}: VISOR: setpgid failed: {:s}", pid as int, os::last_os_error());
+        }
+        util::tcsetpgrp(0, util::getpgrp());
+    }
+
+    fn give_terminal(&self, pgrp: libc::pid_t) {
+        if self.tty {
+            util::tcsetpgrp(0, pgrp);
+        }
+    }
+
+    // ctrl-z stopped TRY. Take the terminal back and stop ourselves, so
+    // the shell sees the whole job stop. When fg (or bg) continues us,
+    // continue TRY, in the foreground if we are.
+    fn suspend(&mut self) {
+        debug2!("{:d}: VISOR: TRY stopped, suspending.", util::getpid() as int);
+        self.give_terminal(util::getpgrp());
+        util::kill(util::getpid(), signum::SIGTSTP);
+
+        if util::tcgetpgrp(0) == util::getpgrp() {
+            self.give_terminal(self.group);
+        }
+        util::kill(-self.group, signum::SIGCONT);
+    }
+
+    // wait for a reply from CUR or TRY, meanwhile moving output from
+    // the pipes, so a chatty snippet can't fill them and block.
+    fn await_reply(&mut self, reply_fd: c_int, twin_done: bool,
+                   twin_out: &mut capture::Output, out: &mut capture::Output) {
+        loop {
+            let ready = util::poll_any([self.out_fd, self.err_fd, reply_fd]);
+            if ready[0] || ready[1] {
+                self.collect_output(twin_done, twin_out, out);
+            }
+            if ready[2] {
+                return;
+            }
+        }
+    }
+
+    // the twin's output is kept for comparing; TRY's is also shown.
+    fn collect_output(&mut self, twin_done: bool,
+                      twin_out: &mut capture::Output, out: &mut capture::Output) {
+        let o = capture::drain(self.out_fd);
+        let e = capture::drain(self.err_fd);
+        if twin_done {
+            io::stdout().write(o);
+            io::stderr().write(e);
+            out.out.push_all(o);
+            out.err.push_all(e);
+        } else {
+            twin_out.out.push_all(o);
+            twin_out.err.push_all(e);
+        }
+    }
+
+    fn recv_reply(&mut self, reply_fd: c_int) -> Option<msg::Reply> {
+        let m = match msg::recv(reply_fd) {
+            None => return None,
+            Some(m) => m,
+        };
+        debug2!("{:d}: I am VISOR: I got a reply: '{:s}'", util::getpid() as int, m);
+        msg::Reply::decode(m)
+    }
+
+    // at the post-mortem> prompt: run expr in a child of the failed
+    // TRY, which is continued for the purpose and stops again after.
+    fn inspect(&mut self, expr: &str, code_fd: c_int, reply_fd: c_int) {
+        let corpse = self.corpse.unwrap();
+        let flags = msg::Flags{ twice: false, post_mortem: false };
+        if !msg::send(code_fd, msg::Code(expr.to_owned(), flags).encode()) {
+            fail2!("{:d}: I am VISOR: failed to send code to the post-mortem: {:s}",
+                   util::getpid() as int, os::last_os_error());
+        }
+        self.give_terminal(self.group);
+        util::kill(corpse, signum::SIGCONT);
+        // what the inspector prints is shown, not kept.
+        let mut none = capture::Output::new();
+        let mut out = capture::Output::new();
+        loop {
+            self.await_reply(reply_fd, true, &mut none, &mut out);
+            let r = self.recv_reply(reply_fd);
+            self.collect_output(true, &mut none, &mut out);
+            match r {
+                Some(msg::Inspected(_, ok)) => {
+                    if !ok {
+                        io::stderr().write_line(format!("{:s}: that failed too; the failed \
+                                                         state is unchanged.", PROGRAM_NAME));
+                    }
+                    break
+                },
+                Some(msg::Stopped(_)) => self.suspend(),
+                r => fail2!("VISOR doesn't recognize reply from the post-mortem: {:?}", r),
+            }
+        }
+        self.give_terminal(util::getpgrp());
+    }
+
+    // .abandon: kill the failed TRY. CUR sees it die and reports as for
+    // any failure; the failure itself was told when it happened.
+    fn abandon(&mut self, reply_fd: c_int) {
+        let corpse = match ::std::util::replace(&mut self.corpse, None) {
+            Some(pid) => pid,
+            None => {
+                println("nothing to abandon: no post-mortem in progress.");
+                return;
+            },
+        };
+        util::kill(corpse, signum::SIGKILL);
+        loop {
+            match self.recv_reply(reply_fd) {
+                Some(msg::Failed(pid, _, effects)) => {
+                    self.cur = pid;
+                    report_effects(effects);
+                    break
+                },
+                Some(msg::Backtrace(*)) => (),
+                r => fail2!("VISOR doesn't recognize reply from CUR: {:?}", r),
+            }
+        }
+    }
+
+    // VISOR is done: ask TRY and CUR to exit cleanly so they flush
+    // their output and run destructors and atexit hooks. Whoever is
+    // still around after the deadline gets SIGKILL.
+    fn shutdown(&mut self, code_fd: c_int, reply_fd: c_int, status: c_int) -> ! {
+        if self.corpse.is_some() {
+            self.abandon(reply_fd);
+        }
+        // snapshots and inactive branches are stopped copies of other
+        // states; there is nothing in them to flush.
+        for &pid in self.snapshots.clear().iter() {
+            util::kill(pid, signum::SIGKILL);
+        }
+        for b in self.branches.mut_iter() {
+            for &pid in b.snapshots.clear().iter() {
+                util::kill(pid, signum::SIGKILL);
+            }
+            util::kill(b.cur, signum::SIGKILL);
+        }
+
+        let deadline = self.config.quit_deadline_ms;
+        let mut clean = msg::send(code_fd, msg::Quit.encode()) &&
+            util::poll_readable(reply_fd, deadline) &&
+            match self.recv_reply(reply_fd) {
+                Some(msg::Quitting(_)) => true,
+                _ => false,
+            };
+
+        // closing our ends also wakes a TRY that missed the quit.
+        os::close(code_fd);
+        os::close(reply_fd);
+
+        clean = util::wait_gone(self.cur, deadline) && clean;
+        if !clean {
+            debug2!("{:d}: VISOR: CUR {:d} did not quit in time, killing group {:d}.",
+                   util::getpid() as int, self.cur as int, self.group as int);
+            util::kill(-self.group, signum::SIGKILL);
+        }
+
+        match self.cgroup {
+            Some(ref cg) => {
+                util::wait_gone(self.cur, deadline);
+                cg.remove();
+            },
+            None => (),
+        }
+
+        util::exit(if !clean && status == EXIT_OK { EXIT_UNCLEAN } else { status })
+    }
+
+    // record code in the history, send it to TRY and wait for the
+    // verdict. Returns true if the code failed.
+    fn transact(&mut self, code: &str, code_fd: c_int, reply_fd: c_int) -> bool {
+        self.cmd.push(code.to_owned());
+
+        debug2!("visor is: {:?}", self);
+
+        // send code over to TRY
+        // no post-mortem in batch mode: there is nobody to inspect.
+        let flags = msg::Flags{ twice: self.twice, post_mortem: self.post_mortem && self.tty };
+        let request = msg::Code(code.to_owned(), flags);
+        if !msg::send(code_fd, request.encode()) {
+            fail2!("{:d}: I am VISOR: failed to send code to TRY: {:s}",
+                   util::getpid() as int, os::last_os_error());
+        }
+
+        // wait for "success" (from TRY) or "failed" (from CUR)
+        debug2!("{:d}: I am VISOR: waiting for success or failed",
+        util::getpid() as int);
+        // the code runs in the foreground: ctrl-c and ctrl-z go to TRY.
+        self.give_terminal(self.group);
+        let failed;
+        // in .twice mode: the twin's outcome once it is known, and its
+        // output; outside it, all output is TRY's.
+        let mut twin = None;
+        let mut bt = None;
+        let mut twin_out = capture::Output::new();
+        let mut out = capture::Output::new();
+        let mut edits = ~[];
+        loop {
+            let twin_done = !self.twice || twin.is_some();
+            self.await_reply(reply_fd, twin_done, &mut twin_out, &mut out);
+            let r = self.recv_reply(reply_fd);
+            // whatever came before a reply belongs to the run it is about.
+            self.collect_output(twin_done, &mut twin_out, &mut out);
+            match r {
+                Some(msg::Twin(_, ok, effects)) => {
+                    // the twin's state is thrown away, whatever it did.
+                    twin = Some(ok);
+                    report_effects(effects);
+                },
+                Some(msg::Backtrace(_, text)) => bt = Some(text),
+                Some(msg::Success(pid, e)) => {
+                    self.commit(pid);
+                    for edit in e.iter() {
+                        callgraph::apply(&mut self.defs.callgraph, edit);
+                    }
+                    edits = e;
+                    failed = false;
+                    break
+                },
+                Some(msg::Failed(pid, why, effects)) => {
+                    self.cur = pid;
+                    failed = true;
+                    // a fail!() has already said why; a signal or a
+                    // limit has not. stderr keeps batch output clean.
+                    match why {
+                        msg::Exited(_) => (),
+                        _ => io::stderr().write_line(
+                            format!("{:s}: code {:s}", PROGRAM_NAME, why.describe())),
+                    }
+                    report_effects(effects);
+                    break
+                },
+                Some(msg::PostMortem(pid)) => {
+                    self.corpse = Some(pid);
+                    failed = true;
+                    io::stderr().write_line(format!("{:s}: the failed state is kept for inspection; \
+                                                     .abandon when done.", PROGRAM_NAME));
+                    break
+                },
+                Some(msg::Stopped(_)) => self.suspend(),
+                r => fail2!("VISOR doesn't recognize reply from CUR/TRY: {:?}", r),
+            }
+        }
+        self.give_terminal(util::getpgrp());
+        match bt {
+            Some(text) => {
+                // the top of the stack here; all of it with .bt.
+                let frames = crash::frames(text);
+                let err = io::stderr();
+                err.write_line(format!("{:s}: where it crashed:", PROGRAM_NAME));
+                for f in frames.iter().take(BT_FRAMES) {
+                    err.write_line(format!("    {:s}", *f));
+                }
+                if frames.len() > BT_FRAMES {
+                    err.write_line(format!("    ... {:u} more; .bt shows them all.",
+                                           frames.len() - BT_FRAMES));
+                }
+                self.backtrace = Some(text);
+            },
+            None => (),
+        }
+        match twin {
+            Some(twin_ok) => {
+                let diffs = twice::compare(twin_ok, &twin_out, !failed, &out);
+                if diffs.len() > 0 {
+                    let err = io::stderr();
+                    err.write_line(format!("{:s}: .twice: the two runs diverged; \
+                                            the code may depend on time, randomness or addresses:",
+                                           PROGRAM_NAME));
+                    for d in diffs.iter() {
+                        err.write_line(format!("    {:s}", *d));
+                    }
+                }
+            },
+            None => (),
+        }
+        self.failed.push(failed);
+        self.outputs.push(out);
+        self.edits.push(edits);
+        failed
+    }
+}
+
+// What the snippets so far defined, kept by CUR and TRY and changed
+// only by running one. It is sent whole into the task that runs a
+// snippet in post-mortem mode, and back out of it.
+struct Defs {
+    /// function dependency graph
+    callgraph: callgraph::BothWayGraph,
+
+    /// the latest definition of each fn in the graph, for checking
+    /// its callers again when it changes.
+    sources: hashmap::HashMap<~str, ~str>,
+    /// the extern mods and uses of every snippet this state is made
+    /// of, which those definitions may need when checked.
+    views: ~[~str],
+
+    /// in TRY: the changes made to the call graph so far, for VISOR.
+    pending: ~[msg::GraphEdit],
+}
+
+impl Defs {
+    fn new() -> Defs {
+        Defs {
+            callgraph: callgraph::BothWayGraph::new(),
+            sources: hashmap::HashMap::new(),
+            views: ~[],
+            pending: ~[],
+        }
+    }
+
+    // TRY: compile and run code; return only if that succeeded.
+    fn run_code(&mut self, code: &str) {
+        match graph_command(code) {
+            Some(cmd) => self.callgraph_exec(cmd),
+            None => {
+                /*
+                 *  here is where call to do the majority of the
+                 *  actual work: compile and run the code.
+                 */
+                let defined = compile::compile_and_run(code);
+                for v in export::view_items(code).move_iter() {
+                    if !self.views.contains(&v) {
+                        self.views.push(v);
+                    }
+                }
+                self.define(defined);
+            },
+        }
+    }
+
+    // TRY, after code compiled and ran: put the items it defined in
+    // the graph we hand on if we become CUR. What uses any it
+    // redefined, a fn, a type or an impl, is type-checked again,
+    // with everything it uses and every extern mod and use so far.
+    // If some of it no longer compiles we fail, and the redefinition
+    // is rolled back with the rest of the snippet. Nothing is jitted
+    // again: callers keep running the code they were compiled with.
+    // Method nodes are the graph's own, with nothing to check.
+    fn define(&mut self, defined: ~[calls::Item]) {
+        let affected = callgraph::record(&mut self.callgraph, defined, &mut self.pending);
+        for d in defined.move_iter() {
+            self.sources.insert(d.name, d.source);
+        }
+        let affected: ~[~str] = affected.move_iter()
+            .filter(|f| self.callgraph.kind_of(*f) != Some(callgraph::Method)).collect();
+        if affected.len() == 0 {
+            return;
+        }
+
+        // a fn that calls a deleted one won't compile; it waits to be
+        // redefined, or for what it calls to be.
+        let broken = self.callgraph.broken_fns();
+        let (waiting, affected) = affected.partitioned(|f| broken.contains(f));
+        if waiting.len() > 0 {
+            io::stderr().write_line(format!("still broken: {:s}", waiting.connect(", ")));
+        }
+        let mut unit: ~[~str] = ~[];
+        for f in affected.iter() {
+            let mut fs = self.callgraph.unit_of(*f);
+            fs.unshift(f.clone());
+            for g in fs.move_iter() {
+                if !unit.contains(&g) {
+                    unit.push(g);
+                }
+            }
+        }
+        // fns that only .g knows have no source; leave them out.
+        let sources: ~[~str] = unit.iter().filter_map(|f| self.sources.find(f))
+            .map(|s| s.clone()).collect();
+        if sources.len() == 0 {
+            return;
+        }
+        // on stderr: what TRY prints is captured as the snippet's output.
+        io::stderr().write_line(format!("checking {:s}", affected.connect(", ")));
+        compile::check(export::unit(self.views, sources));
+    }
+
     fn callgraph_exec(&mut self, code: &str) {
         if code.char_at(0) == 'd' {
-            match self.callgraph.delete(code.slice_from(1).trim_left()) {
+            let func = code.slice_from(1).trim();
+            match self.callgraph.remove(func) {
                 Err(e) => fail2!("TRY code failure: {:?}", e),
-                Ok(*) => print("Deleted function"),
+                Ok(broken) => {
+                    print("Deleted function");
+                    if broken.len() > 0 {
+                        print!("; broken until redefined: {:s}", broken.connect(", "));
+                    }
+                },
             }
+            self.pending.push(msg::Delete(func.to_owned()));
         } else {
             let trimmed_code = code.trim_left();
             match trimmed_code.find_str(": ") {
@@ -346,11 +1614,16 @@ impl Visor {
                 Some(pos) => {
                     let func = trimmed_code.slice_to(pos).to_owned();
                     let rest = trimmed_code.slice_from(pos + 2).trim();
-                    let deps = if rest.len() == 0 {
+                    let deps: ~[&str] = if rest.len() == 0 {
                         ~[]
                     } else {
                         rest.split_iter(',').map(|s| s.trim()).collect()
                     };
+                    self.pending.push(msg::Define(func.clone(), callgraph::Fn, deps.map(|d| d.to_owned())));
+                    // callees not defined yet wait in the graph for
+                    // their definitions.
+                    let missing: ~[&str] = deps.iter().map(|d| *d)
+                        .filter(|d| *d != func.as_slice() && !self.callgraph.contains(&[*d])).collect();
                     if self.callgraph.contains(&[func.as_slice()]) {
                         match self.callgraph.update(func, deps) {
                             Err(e) => fail2!("TRY code failure: {:?}", e),
@@ -359,11 +1632,21 @@ impl Visor {
                             },
                         }
                     } else {
-                        match self.callgraph.add(func, deps) {
+                        match self.callgraph.update(func, deps) {
                             Err(e) => fail2!("TRY code failure: {:?}", e),
-                            Ok(*) => print!("Added new function"),
+                            Ok(affected) => {
+                                print("Added new function");
+                                // it was called before it was defined.
+                                if affected.len() > 0 {
+                                    let fs: ~[~str] = affected.iter().map(|f| (*f).clone()).collect();
+                                    print!("; resolves calls from {:s}", fs.connect(", "));
+                                }
+                            },
                         }
                     }
+                    if missing.len() > 0 {
+                        print!("; not defined yet: {:s}", missing.connect(", "));
+                    }
                 },
             }
         }
@@ -373,36 +1656,116 @@ impl Visor {
     }
 }
 
-// reply with a message at most 32 bytes.
-#[fixed_stack_segment]
-fn pipe32reply(from: &str, replymsg: &str, fd: libc::c_int) -> i64 {
-    static REPLYLEN: uint = 32;
-    assert!(replymsg.len() < REPLYLEN);
-    let mut replybuf = ~[0u8, ..REPLYLEN];
-    vec::bytes::copy_memory(replybuf, replymsg.as_bytes(), replymsg.len());
-    replybuf.truncate(replymsg.len());
-
-    let mut bytes_written: i64;
-    bytes_written = do replybuf.as_mut_buf |ptr, len| {
-        util::write(fd, ptr as *libc::c_void, len as u64)
-    };
-    assert!(bytes_written == replymsg.len() as i64);
+// print a history, with failed commands commented out as .c does.
+fn print_history(cmd: &[~str], failed: &[bool], indent: &str) {
+    for (i, c) in cmd.iter().enumerate() {
+        let not = if i < failed.len() && failed[i] { "//not: " } else { "" };
+        println!("{:s}{:s}{:s}", indent, not, *c);
+    }
+}
+
+// fn names on one line, for .g?.
+fn print_fns(fns: ~[~str]) {
+    if fns.len() == 0 {
+        println("(none)");
+    } else {
+        println(fns.connect(" "));
+    }
+}
+
+// the rest of a .g call graph command, which TRY runs in place of code.
+fn graph_command<'a>(code: &'a str) -> Option<&'a str> {
+    let trimmed_code = code.trim_left();
+    if trimmed_code.char_len() > 2 && trimmed_code.slice_to(2) == ".g" {
+        Some(trimmed_code.slice_from(2))
+    } else {
+        None
+    }
+}
+
+// split a meta command into its first word and the (trimmed) rest.
+fn meta_split<'a>(line: &'a str) -> (&'a str, &'a str) {
+    match line.find(::std::char::is_whitespace) {
+        None => (line, ""),
+        Some(pos) => (line.slice_to(pos), line.slice_from(pos).trim()),
+    }
+}
 
-    if bytes_written < 0 {
-        fail2!("{:d} {:s}: read on pipe_code.out failed with errno: {:?} '{:?}'",
-              util::getpid() as int, from, os::errno(), os::last_os_error());
+// tell the user what a rolled back snippet did that could not be undone.
+fn report_effects(effects: &[~str]) {
+    if effects.len() > 0 {
+        let err = io::stderr();
+        err.write_line(format!("{:s}: rolled back, except that it:", PROGRAM_NAME));
+        for e in effects.iter() {
+            err.write_line(format!("    {:s}", *e));
+        }
     }
+}
 
-    debug2!("{:d}: {:s}: sent replymsg of len '{:?}' with content '{:s}'",
-           util::getpid() as int, from, replymsg.len(), replymsg);
+// read one line of input for VISOR, through the line editor when we
+// have one. None means EOF.
+fn read_line(editor: &mut Option<editline::Editor>, prompt: &str) -> Option<~str> {
+    match *editor {
+        Some(ref mut ed) => {
+            ed.set_prompt(prompt);
+            ed.gets()
+        },
+        None => {
+            print(prompt);
+            let line = io::stdin().read_line();
+            if line.len() == 0 && io::stdin().eof() {
+                None
+            } else {
+                Some(line)
+            }
+        },
+    }
+}
 
-    bytes_written
+// tell VISOR how the last request went. If VISOR is already gone
+// there is nobody left to tell, which is fine.
+fn reply(from: &str, r: msg::Reply, fd: c_int) {
+    let m = r.encode();
+    if !msg::send(fd, m) {
+        debug2!("{:d} {:s}: could not send reply '{:s}': {:s}",
+               util::getpid() as int, from, m, os::last_os_error());
+        return;
+    }
+    debug2!("{:d}: {:s}: sent reply '{:s}'", util::getpid() as int, from, m);
 }
 
 #[fixed_stack_segment]
 fn single_threaded_main() {
+    // rustxi [--sandbox] [--results file] [script.rs]: a script, or
+    // anything piped to stdin, runs in batch mode.
+    let mut args = os::args();
+    let sandbox = match args.iter().position(|a| a.as_slice() == "--sandbox") {
+        Some(i) => { args.remove(i); true },
+        None => false,
+    };
+    let results = match args.iter().position(|a| a.as_slice() == "--results") {
+        Some(i) if i + 1 < args.len() => {
+            args.remove(i);
+            Some(args.remove(i))
+        },
+        Some(_) => {
+            io::stderr().write_line(format!("{:s}: --results needs a file name", PROGRAM_NAME));
+            util::exit(EXIT_NO_SCRIPT)
+        },
+        None => None,
+    };
+    let script = if args.len() > 1 {
+        Some(args[1].clone())
+    } else if !util::isatty(0) {
+        Some(~"-")
+    } else {
+        None
+    };
+
     let mut v = Visor::new();
-    v.start();
+    v.config.sandbox = v.config.sandbox || sandbox;
+    v.results = results;
+    v.start(script);
 }
 
 // if you want to be sure you are running on the main thread, do this:
@@ -0,0 +1,365 @@
+/**
+ *  msg: length-prefixed messages over the VISOR/CUR/TRY pipes.
+ *
+ *  A message is its length in decimal, a newline, then that many
+ *  bytes. Unlike a single write() and read() this survives payloads
+ *  bigger than PIPE_BUF, which the kernel may hand over in pieces.
+ **/
+
+use std::{libc, os, str};
+use std::libc::{c_int, c_void};
+use std::libc::types::os::arch::posix88::{pid_t};
+
+use callgraph;
+use util;
+
+// write all of buf, retrying on short writes and EINTR.
+fn write_all(fd: c_int, buf: &[u8]) -> bool {
+    let mut off = 0u;
+    while off < buf.len() {
+        let n = do buf.slice_from(off).as_imm_buf |ptr, len| {
+            util::write(fd, ptr as *c_void, len as libc::size_t)
+        };
+        if n < 0 {
+            if os::errno() == libc::EINTR as int { continue; }
+            return false;
+        }
+        off += n as uint;
+    }
+    true
+}
+
+// read exactly len bytes; None on EOF or error.
+fn read_exact(fd: c_int, len: uint) -> Option<~[u8]> {
+    let mut buf = ::std::vec::from_elem(len, 0u8);
+    let mut off = 0u;
+    while off < len {
+        let n = do buf.mut_slice_from(off).as_mut_buf |ptr, len| {
+            util::read(fd, ptr as *mut c_void, len as libc::size_t)
+        };
+        if n < 0 && os::errno() == libc::EINTR as int { continue; }
+        if n <= 0 {
+            return None;
+        }
+        off += n as uint;
+    }
+    Some(buf)
+}
+
+/// Send payload as one message. False if the other end is gone.
+pub fn send(fd: c_int, payload: &str) -> bool {
+    let header = format!("{:u}\n", payload.len());
+    write_all(fd, header.as_bytes()) && write_all(fd, payload.as_bytes())
+}
+
+/// Receive one message. None on EOF, i.e. the sender closed its end.
+pub fn recv(fd: c_int) -> Option<~str> {
+    let mut len = 0u;
+    loop {
+        let c = match read_exact(fd, 1) {
+            None => return None,
+            Some(b) => b[0] as char,
+        };
+        if c == '\n' { break; }
+        match ::std::char::to_digit(c, 10) {
+            None => fail2!("{:d}: msg: bad header byte '{:c}' on fd {:d}",
+                           util::getpid() as int, c, fd as int),
+            Some(d) => len = len * 10 + d,
+        }
+    }
+    read_exact(fd, len).map_move(|b| str::from_utf8(b))
+}
+
+/// How TRY is to run a piece of code.
+#[deriving(Eq)]
+pub struct Flags {
+    /// run it in a twin first, for .twice.
+    twice: bool,
+    /// on failure, stop and wait to be inspected, for .postmortem.
+    post_mortem: bool,
+}
+
+/// What VISOR sends to TRY.
+pub enum Request {
+    /// code to try out.
+    Code(~str, Flags),
+    /// shut down: TRY and then CUR exit cleanly.
+    Quit,
+    /// fork a stopped copy of the current state for a new branch.
+    Branch,
+    /// VISOR is switching branches: TRY exits and CUR stops itself.
+    Suspend,
+}
+
+impl Request {
+    pub fn encode(&self) -> ~str {
+        match *self {
+            Code(ref code, flags) => format!("code{:s}{:s}\n{:s}",
+                                             if flags.twice { " twice" } else { "" },
+                                             if flags.post_mortem { " post-mortem" } else { "" },
+                                             *code),
+            Quit => ~"quit",
+            Branch => ~"branch",
+            Suspend => ~"suspend",
+        }
+    }
+
+    pub fn decode(s: &str) -> Option<Request> {
+        if s == "quit" {
+            Some(Quit)
+        } else if s == "branch" {
+            Some(Branch)
+        } else if s == "suspend" {
+            Some(Suspend)
+        } else if s.starts_with("code") {
+            // code [twice] [post-mortem], then the code on the next line.
+            let nl = match s.find('\n') {
+                None => return None,
+                Some(nl) => nl,
+            };
+            let mut flags = Flags { twice: false, post_mortem: false };
+            for w in s.slice(4, nl).word_iter() {
+                match w {
+                    "twice" => flags.twice = true,
+                    "post-mortem" => flags.post_mortem = true,
+                    _ => return None,
+                }
+            }
+            Some(Code(s.slice_from(nl + 1).to_owned(), flags))
+        } else {
+            None
+        }
+    }
+}
+
+/// Why TRY failed, as far as CUR can tell from its wait status.
+#[deriving(Eq)]
+pub enum Failure {
+    /// TRY exited with this status: fail!(), or the code called exit.
+    Exited(int),
+    /// TRY was killed by this signal.
+    Signaled(int),
+    /// TRY went over a resource limit: cpu, memory, fsize or pids.
+    OverLimit(~str),
+    /// the sandbox refused TRY this syscall.
+    Denied(~str),
+}
+
+impl Failure {
+    fn encode(&self) -> ~str {
+        match *self {
+            Exited(st) => format!("exit {:d}", st),
+            Signaled(sig) => format!("signal {:d}", sig),
+            OverLimit(ref which) => format!("limit {:s}", *which),
+            Denied(ref syscall) => format!("denied {:s}", *syscall),
+        }
+    }
+
+    fn decode(words: &[&str]) -> Option<Failure> {
+        if words.len() != 2 {
+            return None;
+        }
+        match words[0] {
+            "exit" => from_str::<int>(words[1]).map_move(|st| Exited(st)),
+            "signal" => from_str::<int>(words[1]).map_move(|sig| Signaled(sig)),
+            "limit" => Some(OverLimit(words[1].to_owned())),
+            "denied" => Some(Denied(words[1].to_owned())),
+            _ => None,
+        }
+    }
+
+    /// For the user: what happened to TRY.
+    pub fn describe(&self) -> ~str {
+        match *self {
+            Exited(st) => format!("exited with status {:d}", st),
+            Signaled(sig) => format!("killed by signal {:d}", sig),
+            OverLimit(ref which) => format!("over the {:s} limit", *which),
+            Denied(ref syscall) => format!("denied by the sandbox: {:s}()", *syscall),
+        }
+    }
+}
+
+/// A change TRY made to its call graph. VISOR makes the same changes
+/// to its own graph when TRY succeeds.
+#[deriving(Eq, Clone)]
+pub enum GraphEdit {
+    /// the item, of this kind, now uses these: added, or what it used
+    /// replaced.
+    Define(~str, callgraph::Kind, ~[~str]),
+    /// the fn was deleted; what called it is left broken.
+    Delete(~str),
+}
+
+// fields are tab separated: an impl's name has spaces in it.
+impl GraphEdit {
+    fn encode(&self) -> ~str {
+        match *self {
+            Define(ref f, kind, ref deps) => {
+                let mut s = format!("define\t{:s}\t{:s}", kind.name(), *f);
+                for d in deps.iter() {
+                    s.push_char('\t');
+                    s.push_str(*d);
+                }
+                s
+            },
+            Delete(ref f) => format!("delete\t{:s}", *f),
+        }
+    }
+
+    fn decode(line: &str) -> Option<GraphEdit> {
+        let fields: ~[&str] = line.split_iter('\t').collect();
+        if fields.len() < 2 {
+            return None;
+        }
+        match fields[0] {
+            "define" if fields.len() >= 3 => match callgraph::kind_named(fields[1]) {
+                Some(kind) => Some(Define(fields[2].to_owned(), kind,
+                                          fields.slice_from(3).map(|d| d.to_owned()))),
+                None => None,
+            },
+            "delete" if fields.len() == 2 => Some(Delete(fields[1].to_owned())),
+            _ => None,
+        }
+    }
+}
+
+/// What CUR or TRY tells VISOR when a request is done with.
+#[deriving(Eq)]
+pub enum Reply {
+    /// TRY ran the code without failing and is now CUR; its pid, and
+    /// the changes the code made to the call graph.
+    Success(pid_t, ~[GraphEdit]),
+    /// TRY died, and why, leaving behind these external effects (see
+    /// trace.rs); CUR, with this pid, will fork a new TRY.
+    Failed(pid_t, Failure, ~[~str]),
+    /// CUR, with this pid, is exiting because VISOR asked it to.
+    Quitting(pid_t),
+    /// TRY was stopped (ctrl-z); CUR, with this pid, keeps waiting for it.
+    Stopped(pid_t),
+    /// TRY forked the stopped copy for a new branch; the copy's pid.
+    Branched(pid_t),
+    /// CUR, with this pid, is stopping itself until its branch is active.
+    Suspended(pid_t),
+    /// the twin, a first TRY of CUR's for .twice, with this pid, has
+    /// finished: whether it succeeded, and its external effects. CUR
+    /// forks the TRY that runs the code for real next.
+    Twin(pid_t, bool, ~[~str]),
+    /// TRY crashed, and CUR, with this pid, has gdb's backtrace of it;
+    /// the failed reply follows.
+    Backtrace(pid_t, ~str),
+    /// TRY, with this pid, failed in post-mortem mode and has stopped
+    /// itself to be inspected.
+    PostMortem(pid_t),
+    /// a post-mortem inspection forked from the failed TRY with this
+    /// pid is done, and whether it succeeded.
+    Inspected(pid_t, bool),
+}
+
+impl Reply {
+    pub fn encode(&self) -> ~str {
+        match *self {
+            Success(pid, ref edits) => {
+                // one edit per line after the first.
+                let mut s = format!("success {:d}", pid as int);
+                for e in edits.iter() {
+                    s.push_char('\n');
+                    s.push_str(e.encode());
+                }
+                s
+            },
+            Failed(pid, ref why, ref effects) => {
+                // one effect per line after the first.
+                let mut s = format!("failed {:d} {:s}", pid as int, why.encode());
+                for e in effects.iter() {
+                    s.push_char('\n');
+                    s.push_str(*e);
+                }
+                s
+            },
+            Quitting(pid) => format!("quit {:d}", pid as int),
+            Stopped(pid) => format!("stopped {:d}", pid as int),
+            Branched(pid) => format!("branched {:d}", pid as int),
+            Suspended(pid) => format!("suspended {:d}", pid as int),
+            Backtrace(pid, ref text) => format!("backtrace {:d}\n{:s}", pid as int, *text),
+            Twin(pid, ok, ref effects) => {
+                let mut s = format!("twin {:d} {:s}", pid as int, if ok { "ok" } else { "failed" });
+                for e in effects.iter() {
+                    s.push_char('\n');
+                    s.push_str(*e);
+                }
+                s
+            },
+            PostMortem(pid) => format!("post-mortem {:d}", pid as int),
+            Inspected(pid, ok) => format!("inspected {:d} {:s}", pid as int,
+                                          if ok { "ok" } else { "failed" }),
+        }
+    }
+
+    pub fn decode(s: &str) -> Option<Reply> {
+        let mut lines = s.split_iter('\n');
+        let words: ~[&str] = match lines.next() {
+            None => return None,
+            Some(first) => first.word_iter().collect(),
+        };
+        if words.len() < 2 {
+            return None;
+        }
+        if words[0] == "failed" {
+            return match (from_str::<pid_t>(words[1]), Failure::decode(words.slice_from(2))) {
+                (Some(pid), Some(why)) => {
+                    let effects = lines.map(|l| l.to_owned()).collect();
+                    Some(Failed(pid, why, effects))
+                },
+                _ => None,
+            };
+        }
+        if words[0] == "success" && words.len() == 2 {
+            let mut edits = ~[];
+            for l in lines {
+                match GraphEdit::decode(l) {
+                    Some(e) => edits.push(e),
+                    None => return None,
+                }
+            }
+            return match from_str::<pid_t>(words[1]) {
+                Some(pid) => Some(Success(pid, edits)),
+                None => None,
+            };
+        }
+        if words[0] == "backtrace" && words.len() == 2 {
+            let text = lines.collect::<~[&str]>().connect("\n");
+            return from_str::<pid_t>(words[1]).map_move(|pid| Backtrace(pid, text));
+        }
+        if (words[0] == "twin" || words[0] == "inspected") && words.len() == 3 {
+            let ok = match words[2] {
+                "ok" => true,
+                "failed" => false,
+                _ => return None,
+            };
+            let pid = match from_str::<pid_t>(words[1]) {
+                None => return None,
+                Some(p) => p,
+            };
+            if words[0] == "inspected" {
+                return Some(Inspected(pid, ok));
+            }
+            // one effect per line after the first, as for failed.
+            return Some(Twin(pid, ok, lines.map(|l| l.to_owned()).collect()));
+        }
+        if words.len() != 2 {
+            return None;
+        }
+        let pid = match from_str::<pid_t>(words[1]) {
+            None => return None,
+            Some(p) => p,
+        };
+        match words[0] {
+            "quit" => Some(Quitting(pid)),
+            "stopped" => Some(Stopped(pid)),
+            "branched" => Some(Branched(pid)),
+            "suspended" => Some(Suspended(pid)),
+            "post-mortem" => Some(PostMortem(pid)),
+            _ => None,
+        }
+    }
+}
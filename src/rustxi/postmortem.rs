@@ -0,0 +1,59 @@
+/**
+ *  postmortem: keep a failed TRY around to look at.
+ *
+ *  In .postmortem mode TRY runs the code in a task of its own, so a
+ *  fail!() ends the task but not the process. TRY then tells VISOR,
+ *  and stops itself with SIGSTOP, holding the state the code left
+ *  behind. For each expression typed at the post-mortem> prompt VISOR
+ *  continues it; it forks an inspector, which reads the expression
+ *  from the code pipe and runs it, reports how that went and stops
+ *  again. The failed state itself is never changed, so an inspection
+ *  that fails costs nothing. .abandon kills it; CUR then sees its TRY
+ *  die, and everything goes on as after any failure.
+ **/
+
+use std::task;
+use std::libc::{c_int};
+
+use compile;
+use msg;
+use signum;
+use util;
+
+/// Run what TRY does with a snippet, catching its failure instead of
+/// dying of it. What it returns, if it succeeded.
+pub fn try_run<T:Send>(run: ~fn:Send() -> T) -> Option<T> {
+    let r = do task::try {
+        run()
+    };
+    match r {
+        Ok(v) => Some(v),
+        Err(_) => None,
+    }
+}
+
+/// The failed TRY's life from here on: wait to be inspected, until
+/// VISOR kills us.
+pub fn linger(code_fd: c_int, reply_fd: c_int) -> ! {
+    let me = util::getpid();
+    msg::send(reply_fd, msg::PostMortem(me).encode());
+    loop {
+        // VISOR continues us for each inspection.
+        util::kill(me, signum::SIGSTOP);
+
+        let pid = util::fork();
+        if pid == 0 {
+            util::set_parent_death_signal(signum::SIGKILL);
+            match msg::recv(code_fd).and_then(|m| msg::Request::decode(m)) {
+                Some(msg::Code(code, _)) => {
+                    compile::compile_and_run(code);
+                    util::exit(0)
+                },
+                _ => util::exit(1),
+            }
+        }
+        let mut status = 0 as c_int;
+        util::waitpid(pid, &mut status);
+        msg::send(reply_fd, msg::Inspected(me, util::exit_status(status) == Some(0)).encode());
+    }
+}
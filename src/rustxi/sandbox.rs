@@ -0,0 +1,325 @@
+/**
+ *  sandbox: --sandbox mode, for running other people's snippets.
+ *
+ *  Before it runs code, TRY moves into new user, mount and network
+ *  namespaces (so no network, and mounts it makes stay its own), then
+ *  installs a seccomp filter that allows only the syscalls on the
+ *  allowlist. A denied syscall traps to a SIGSYS handler which tells
+ *  CUR the syscall number over a pipe and exits with DENIED_STATUS;
+ *  CUR reports that to VISOR as its own kind of failure.
+ *
+ *  Neither can be undone, so a TRY that succeeds carries the sandbox
+ *  into its life as CUR, and every TRY forked from it inherits it.
+ *  Only a TRY forked from an unsandboxed CUR has to enter it.
+ *
+ *  Linux on x86_64 only: the syscall numbers below are from
+ *  /usr/include/x86_64-linux-gnu/asm/unistd_64.h.
+ **/
+
+use std::{cast, io, libc, os, path, ptr, vec};
+use std::libc::{c_int, c_ulong, c_void};
+
+use signum;
+use util;
+
+pub mod ll {
+    use std::libc::{c_char, c_int, c_ulong, c_void};
+
+    pub struct sock_filter {
+        code: u16,
+        jt: u8,
+        jf: u8,
+        k: u32,
+    }
+
+    pub struct sock_fprog {
+        len: u16,
+        filter: *sock_filter,
+    }
+
+    pub static CLONE_NEWNS: c_int = 0x00020000;
+    pub static CLONE_NEWUSER: c_int = 0x10000000;
+    pub static CLONE_NEWNET: c_int = 0x40000000;
+
+    pub static MS_REC: c_ulong = 16384;
+    pub static MS_PRIVATE: c_ulong = 1 << 18;
+
+    pub static PR_SET_NO_NEW_PRIVS: c_int = 38;
+    pub static PR_SET_SECCOMP: c_int = 22;
+    pub static SECCOMP_MODE_FILTER: c_ulong = 2;
+
+    pub static SECCOMP_RET_KILL_PROCESS: u32 = 0x80000000;
+    pub static SECCOMP_RET_TRAP: u32 = 0x00030000;
+    pub static SECCOMP_RET_ALLOW: u32 = 0x7fff0000;
+
+    pub static AUDIT_ARCH_X86_64: u32 = 0xc000003e;
+
+    // offsets into struct seccomp_data
+    pub static SECCOMP_DATA_NR: u32 = 0;
+    pub static SECCOMP_DATA_ARCH: u32 = 4;
+
+    // the low half of syscall argument i is at SECCOMP_DATA_ARGS + 8 * i
+    pub static SECCOMP_DATA_ARGS: u32 = 16;
+
+    pub static BPF_LD_W_ABS: u16 = 0x20;
+    pub static BPF_JMP_JEQ_K: u16 = 0x15;
+    pub static BPF_JMP_JSET_K: u16 = 0x45;
+    pub static BPF_RET_K: u16 = 0x06;
+
+    pub static SA_SIGINFO: c_int = 4;
+
+    pub static O_CREAT: u32 = 0x40;
+    pub static O_TRUNC: u32 = 0x200;
+
+    // offset of si_syscall in a SIGSYS siginfo_t
+    pub static SI_SYSCALL_OFFSET: uint = 24;
+
+    extern {
+        pub fn unshare(flags: c_int) -> c_int;
+        pub fn mount(source: *c_char, target: *c_char, fstype: *c_char,
+                     flags: c_ulong, data: *c_void) -> c_int;
+        pub fn _exit(status: c_int) -> !;
+    }
+}
+
+/// TRY's exit status when the sandbox denied it a syscall.
+pub static DENIED_STATUS: c_int = 66;
+
+static SYSCALLS: &'static [(&'static str, int)] = &[
+    ("read", 0), ("write", 1), ("open", 2), ("close", 3), ("stat", 4), ("fstat", 5),
+    ("lstat", 6), ("poll", 7), ("lseek", 8), ("mmap", 9), ("mprotect", 10), ("munmap", 11),
+    ("brk", 12), ("rt_sigaction", 13), ("rt_sigprocmask", 14), ("rt_sigreturn", 15),
+    ("ioctl", 16), ("pread64", 17), ("pwrite64", 18), ("readv", 19), ("writev", 20),
+    ("access", 21), ("pipe", 22), ("select", 23), ("sched_yield", 24), ("mremap", 25),
+    ("msync", 26), ("mincore", 27), ("madvise", 28), ("dup", 32), ("dup2", 33),
+    ("pause", 34), ("nanosleep", 35), ("getitimer", 36), ("alarm", 37), ("setitimer", 38),
+    ("getpid", 39), ("socket", 41), ("connect", 42), ("accept", 43), ("sendto", 44),
+    ("recvfrom", 45), ("sendmsg", 46), ("recvmsg", 47), ("shutdown", 48), ("bind", 49),
+    ("listen", 50), ("getsockname", 51), ("getpeername", 52), ("socketpair", 53),
+    ("setsockopt", 54), ("getsockopt", 55), ("clone", 56), ("fork", 57), ("vfork", 58),
+    ("execve", 59), ("exit", 60), ("wait4", 61), ("kill", 62), ("uname", 63), ("fcntl", 72),
+    ("flock", 73), ("fsync", 74), ("fdatasync", 75), ("truncate", 76), ("ftruncate", 77),
+    ("getdents", 78), ("getcwd", 79), ("chdir", 80), ("fchdir", 81), ("rename", 82),
+    ("mkdir", 83), ("rmdir", 84), ("creat", 85), ("link", 86), ("unlink", 87),
+    ("symlink", 88), ("readlink", 89), ("chmod", 90), ("fchmod", 91), ("umask", 95),
+    ("gettimeofday", 96), ("getrlimit", 97), ("getrusage", 98), ("sysinfo", 99),
+    ("times", 100), ("ptrace", 101), ("getuid", 102), ("getgid", 104), ("setuid", 105),
+    ("setgid", 106), ("geteuid", 107), ("getegid", 108), ("setpgid", 109), ("getppid", 110),
+    ("getpgrp", 111), ("setsid", 112), ("getpgid", 121), ("getsid", 124),
+    ("sigaltstack", 131), ("personality", 135), ("mlock", 149), ("munlock", 150),
+    ("prctl", 157), ("arch_prctl", 158), ("setrlimit", 160), ("chroot", 161), ("mount", 165),
+    ("umount2", 166), ("reboot", 169), ("init_module", 175), ("delete_module", 176),
+    ("gettid", 186), ("time", 201), ("futex", 202), ("sched_getaffinity", 204),
+    ("getdents64", 217), ("set_tid_address", 218), ("clock_gettime", 228),
+    ("clock_getres", 229), ("clock_nanosleep", 230), ("exit_group", 231), ("tgkill", 234),
+    ("kexec_load", 246), ("openat", 257), ("mkdirat", 258), ("newfstatat", 262),
+    ("unlinkat", 263), ("renameat", 264), ("readlinkat", 267), ("faccessat", 269),
+    ("unshare", 272), ("set_robust_list", 273), ("get_robust_list", 274), ("dup3", 292),
+    ("pipe2", 293), ("perf_event_open", 298), ("prlimit64", 302), ("setns", 308),
+    ("process_vm_readv", 310), ("process_vm_writev", 311), ("seccomp", 317),
+    ("getrandom", 318), ("memfd_create", 319), ("bpf", 321), ("execveat", 322),
+    ("statx", 332), ("rseq", 334), ("clone3", 435), ("faccessat2", 439),
+];
+
+/// What the sandbox allows unless the rc file says otherwise: enough to
+/// compile and run code, read files and write to open fds, and for a
+/// successful TRY to go on as CUR (fork, wait, signals, limits). No
+/// network, no exec, no tracing or mounting. Some of these are only
+/// allowed with certain arguments, see guard().
+pub static DEFAULT_ALLOW: &'static [&'static str] = &[
+    "read", "write", "open", "openat", "close", "stat", "fstat", "lstat", "newfstatat",
+    "statx", "lseek", "pread64", "pwrite64", "readv", "writev", "access", "faccessat",
+    "faccessat2", "readlink", "readlinkat", "getdents", "getdents64", "getcwd", "fcntl",
+    "ioctl", "dup", "dup2", "dup3", "pipe", "pipe2", "poll", "select",
+    "mmap", "mprotect", "munmap", "mremap", "madvise", "brk", "mincore",
+    "rt_sigaction", "rt_sigprocmask", "rt_sigreturn", "sigaltstack",
+    "clone", "fork", "wait4", "kill", "tgkill", "exit", "exit_group",
+    "getpid", "getppid", "gettid", "getpgrp", "getuid", "getgid", "geteuid", "getegid",
+    "getrlimit", "setrlimit", "prlimit64", "getrusage", "prctl", "arch_prctl",
+    "futex", "set_robust_list", "set_tid_address", "rseq", "sched_yield",
+    "sched_getaffinity", "nanosleep", "clock_nanosleep", "clock_gettime", "clock_getres",
+    "gettimeofday", "time", "times", "uname", "sysinfo", "getrandom",
+];
+
+/// The x86_64 number of the syscall with this name.
+pub fn syscall_number(name: &str) -> Option<int> {
+    SYSCALLS.iter().find(|&&(n, _)| n == name).map_move(|&(_, nr)| nr)
+}
+
+/// The name of syscall nr, or its number if it is not in our table.
+pub fn syscall_name(nr: int) -> ~str {
+    match SYSCALLS.iter().find(|&&(_, k)| k == nr) {
+        Some(&(n, _)) => n.to_owned(),
+        None => format!("syscall-{:d}", nr),
+    }
+}
+
+// where the SIGSYS handler writes the denied syscall's number.
+static mut DENIED_FD: c_int = -1;
+
+#[fixed_stack_segment]
+extern "C" fn sigsys_handler(_sig: c_int, info: *u8, _ctx: *c_void) {
+    // only async-signal-safe calls in here, and only allowed syscalls.
+    unsafe {
+        let nr: i32 = *(ptr::offset(info, ll::SI_SYSCALL_OFFSET as int) as *i32);
+        libc::write(DENIED_FD, cast::transmute(&nr), 4);
+        ll::_exit(DENIED_STATUS);
+    }
+}
+
+fn stmt(code: u16, k: u32) -> ll::sock_filter {
+    ll::sock_filter { code: code, jt: 0, jf: 0, k: k }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> ll::sock_filter {
+    ll::sock_filter { code: code, jt: jt, jf: jf, k: k }
+}
+
+fn load_arg(i: u32) -> ll::sock_filter {
+    stmt(ll::BPF_LD_W_ABS, ll::SECCOMP_DATA_ARGS + 8 * i)
+}
+
+// The checks on the arguments of an allowed syscall, if it has any; they
+// end in a return either way. open and openat may not create or truncate
+// files. kill may signal only our own group (pid 0 or -group) or a single
+// process other than VISOR: a filter cannot tell which group a pid is in,
+// and CUR stops itself by pid. tgkill likewise may not reach VISOR.
+fn guard(name: &str, visor: libc::pid_t, group: libc::pid_t) -> Option<~[ll::sock_filter]> {
+    let no_create = |flags: u32| ~[
+        load_arg(flags),
+        jump(ll::BPF_JMP_JSET_K, ll::O_CREAT | ll::O_TRUNC, 0, 1),
+        stmt(ll::BPF_RET_K, ll::SECCOMP_RET_TRAP),
+        stmt(ll::BPF_RET_K, ll::SECCOMP_RET_ALLOW),
+    ];
+    match name {
+        "open" => Some(no_create(1)),
+        "openat" => Some(no_create(2)),
+        "kill" => Some(~[
+            load_arg(0),
+            jump(ll::BPF_JMP_JEQ_K, 0, 4, 0),
+            jump(ll::BPF_JMP_JEQ_K, (-group) as u32, 3, 0),
+            // any other group, or -1 for everyone
+            jump(ll::BPF_JMP_JSET_K, 0x80000000, 1, 0),
+            jump(ll::BPF_JMP_JEQ_K, visor as u32, 0, 1),
+            stmt(ll::BPF_RET_K, ll::SECCOMP_RET_TRAP),
+            stmt(ll::BPF_RET_K, ll::SECCOMP_RET_ALLOW),
+        ]),
+        "tgkill" => Some(~[
+            load_arg(0),
+            jump(ll::BPF_JMP_JEQ_K, visor as u32, 0, 1),
+            stmt(ll::BPF_RET_K, ll::SECCOMP_RET_TRAP),
+            stmt(ll::BPF_RET_K, ll::SECCOMP_RET_ALLOW),
+        ]),
+        _ => None,
+    }
+}
+
+fn filter_for(allow: &[~str], visor: libc::pid_t, group: libc::pid_t) -> ~[ll::sock_filter] {
+    let mut prog = ~[];
+    // refuse anything that isn't a native x86_64 syscall outright.
+    prog.push(stmt(ll::BPF_LD_W_ABS, ll::SECCOMP_DATA_ARCH));
+    prog.push(jump(ll::BPF_JMP_JEQ_K, ll::AUDIT_ARCH_X86_64, 1, 0));
+    prog.push(stmt(ll::BPF_RET_K, ll::SECCOMP_RET_KILL_PROCESS));
+    prog.push(stmt(ll::BPF_LD_W_ABS, ll::SECCOMP_DATA_NR));
+    for name in allow.iter() {
+        match syscall_number(*name) {
+            Some(nr) => match guard(*name, visor, group) {
+                // the checks leave the syscall number behind in A, but
+                // they all return, so the next comparison never sees it.
+                Some(checks) => {
+                    prog.push(jump(ll::BPF_JMP_JEQ_K, nr as u32, 0, checks.len() as u8));
+                    prog.push_all_move(checks);
+                },
+                None => {
+                    prog.push(jump(ll::BPF_JMP_JEQ_K, nr as u32, 0, 1));
+                    prog.push(stmt(ll::BPF_RET_K, ll::SECCOMP_RET_ALLOW));
+                },
+            },
+            None => (),
+        }
+    }
+    prog.push(stmt(ll::BPF_RET_K, ll::SECCOMP_RET_TRAP));
+    prog
+}
+
+fn write_proc(file: &str, value: &str) -> Result<(), ~str> {
+    match io::file_writer(&path::Path(file), [io::Truncate]) {
+        Ok(w) => { w.write_str(value); Ok(()) },
+        Err(e) => Err(format!("cannot write {:s}: {:s}", file, e)),
+    }
+}
+
+/// Move TRY into the sandbox. denied_fd is where a denied syscall gets
+/// reported; visor is the one pid outside our group it may not signal.
+/// On error TRY must not run the code.
+#[fixed_stack_segment]
+pub fn enter(allow: &[~str], denied_fd: c_int, visor: libc::pid_t) -> Result<(), ~str> {
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+    unsafe {
+        if ll::unshare(ll::CLONE_NEWUSER | ll::CLONE_NEWNS | ll::CLONE_NEWNET) != 0 {
+            return Err(format!("unshare: {:s}", os::last_os_error()));
+        }
+    }
+    // keep our own uid and gid inside the new user namespace.
+    match write_proc("/proc/self/setgroups", "deny") {
+        Err(e) => return Err(e),
+        Ok(()) => (),
+    }
+    match write_proc("/proc/self/uid_map", format!("{:u} {:u} 1", uid as uint, uid as uint)) {
+        Err(e) => return Err(e),
+        Ok(()) => (),
+    }
+    match write_proc("/proc/self/gid_map", format!("{:u} {:u} 1", gid as uint, gid as uint)) {
+        Err(e) => return Err(e),
+        Ok(()) => (),
+    }
+    // mounts made in here must not propagate back out.
+    let r = do "/".with_c_str |root| {
+        unsafe { ll::mount(ptr::null(), root, ptr::null(), ll::MS_REC | ll::MS_PRIVATE, ptr::null()) }
+    };
+    if r != 0 {
+        return Err(format!("mount: {:s}", os::last_os_error()));
+    }
+
+    unsafe {
+        DENIED_FD = denied_fd;
+        let act = util::ll::sigaction {
+            sa_sigaction: cast::transmute(sigsys_handler),
+            sa_mask: [0, ..16],
+            sa_flags: ll::SA_SIGINFO,
+            sa_restorer: ptr::null(),
+        };
+        if util::ll::sigaction(signum::SIGSYS, &act, ptr::mut_null()) != 0 {
+            return Err(format!("sigaction: {:s}", os::last_os_error()));
+        }
+    }
+
+    let prog = filter_for(allow, visor, util::getpgrp());
+    let fprog = ll::sock_fprog { len: prog.len() as u16, filter: vec::raw::to_ptr(prog) };
+    unsafe {
+        if util::ll::prctl(ll::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(format!("no_new_privs: {:s}", os::last_os_error()));
+        }
+        if util::ll::prctl(ll::PR_SET_SECCOMP, ll::SECCOMP_MODE_FILTER,
+                           &fprog as *ll::sock_fprog as c_ulong) != 0 {
+            return Err(format!("seccomp: {:s}", os::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// CUR, after TRY exited with DENIED_STATUS: the name of the syscall
+/// the sandbox refused it. None if TRY didn't say, i.e. the code just
+/// happened to exit with that status.
+pub fn denied_syscall(denied_fd: c_int) -> Option<~str> {
+    if !util::poll_readable(denied_fd, 0) {
+        return None;
+    }
+    let mut nr = 0i32;
+    let n = util::read(denied_fd, unsafe { cast::transmute(&mut nr) }, 4);
+    if n != 4 {
+        return None;
+    }
+    Some(syscall_name(nr as int))
+}
@@ -0,0 +1,89 @@
+/**
+ *  script: non-interactive (batch) mode, for `rustxi file.rs` and for
+ *  input piped to stdin.
+ *
+ *  The input is cut into chunks at blank lines outside any brackets,
+ *  so a multi-line item such as a fn definition travels to TRY as one
+ *  transaction, blank lines in its body and all. With --results file,
+ *  each chunk's outcome is written there as one line of JSON:
+ *
+ *    {"chunk":2,"line":5,"result":"failed"}
+ *
+ *  where line is the chunk's first line in the input. They go to a
+ *  file of their own because stdout is the snippets', who may print
+ *  anything, lines that look like these included.
+ **/
+
+use std::{io, path};
+
+use lexer;
+
+pub struct Chunk {
+    /// 1-based, in input order.
+    num: uint,
+    /// first line of the chunk in the input, 1-based.
+    line: uint,
+    code: ~str,
+}
+
+/// Cut text into chunks: runs of lines ended by a blank line, unless
+/// that falls inside brackets, a string or a block comment.
+pub fn chunks(text: &str) -> ~[Chunk] {
+    let toks = lexer::tokenize(text);
+    let mut next = 0u;
+    let mut depth = 0;
+    let mut out = ~[];
+    let mut cur: ~[&str] = ~[];
+    let mut first = 0u;
+    let mut lineno = 0u;
+    let mut at = 0u;
+    for line in text.split_iter('\n') {
+        lineno += 1;
+        // the brackets before this line, and whether a token runs
+        // into it.
+        while next < toks.len() && toks[next].hi <= at {
+            match toks[next].kind {
+                lexer::Open => depth += 1,
+                lexer::Close => depth -= 1,
+                _ => (),
+            }
+            next += 1;
+        }
+        let inside = next < toks.len() && toks[next].lo < at;
+        at += line.len() + 1;
+        if line.trim().len() == 0 && depth <= 0 && !inside {
+            if cur.len() > 0 {
+                out.push(Chunk { num: out.len() + 1, line: first, code: cur.connect("\n") });
+                cur = ~[];
+            }
+            continue;
+        }
+        if cur.len() == 0 {
+            if line.trim().len() == 0 {
+                continue;
+            }
+            first = lineno;
+        }
+        cur.push(line);
+    }
+    if cur.len() > 0 {
+        out.push(Chunk { num: out.len() + 1, line: first, code: cur.connect("\n") });
+    }
+    out
+}
+
+/// Read the whole script: from path, or from stdin when path is "-".
+pub fn read(path: &str) -> Result<~str, ~str> {
+    if path == "-" {
+        let bytes = io::stdin().read_whole_stream();
+        Ok(::std::str::from_utf8(bytes))
+    } else {
+        io::read_whole_file_str(&path::Path(path))
+    }
+}
+
+/// The machine-readable result line for one chunk.
+pub fn report(chunk: &Chunk, failed: bool) -> ~str {
+    format!("\\{\"chunk\":{:u},\"line\":{:u},\"result\":\"{:s}\"\\}",
+            chunk.num, chunk.line, if failed { "failed" } else { "ok" })
+}
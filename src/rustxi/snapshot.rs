@@ -0,0 +1,89 @@
+/**
+ *  snapshot: VISOR's bookkeeping for retained states.
+ *
+ *  When TRY succeeds it becomes CUR, and the old CUR, instead of being
+ *  killed, can be kept as a snapshot: a stopped process still sitting
+ *  in waitpid() on its child. Reverting to a snapshot needs no replay.
+ *  Kill everything forked after it, SIGCONT it, and it sees its
+ *  child die and carries on as CUR, exactly as after a failed TRY.
+ **/
+
+use std::libc::types::os::arch::posix88::{pid_t};
+
+pub struct Snapshot {
+    pid: pid_t,
+    /// how much of Visor.cmd this state has seen: the successful
+    /// commands in cmd[..ncmd] are exactly what ran in it.
+    ncmd: uint,
+    /// set by .checkpoint; named snapshots are never evicted.
+    name: Option<~str>,
+}
+
+/// Snapshots, oldest first. Each one is the parent of the next, and
+/// the newest is the parent of the current CUR.
+pub struct Snapshots {
+    list: ~[Snapshot],
+}
+
+impl Snapshots {
+    pub fn new() -> Snapshots {
+        Snapshots { list: ~[] }
+    }
+
+    pub fn len(&self) -> uint {
+        self.list.len()
+    }
+
+    pub fn iter<'a>(&'a self) -> ::std::vec::VecIterator<'a, Snapshot> {
+        self.list.iter()
+    }
+
+    /// Add the newest snapshot, then evict the oldest unnamed ones
+    /// until at most keep unnamed ones remain. Returns the pids of the
+    /// evicted snapshots, for the caller to kill.
+    pub fn push(&mut self, snap: Snapshot, keep: uint) -> ~[pid_t] {
+        self.list.push(snap);
+        let mut unnamed = self.list.iter().count(|s| s.name.is_none());
+        let mut evicted = ~[];
+        let mut kept = ~[];
+        for s in self.list.move_iter() {
+            if s.name.is_none() && unnamed > keep {
+                unnamed -= 1;
+                evicted.push(s.pid);
+            } else {
+                kept.push(s);
+            }
+        }
+        self.list = kept;
+        evicted
+    }
+
+    /// Position of the snapshot with this name.
+    pub fn find(&self, name: &str) -> Option<uint> {
+        do self.list.iter().position |s| {
+            match s.name {
+                Some(ref n) => n.as_slice() == name,
+                None => false,
+            }
+        }
+    }
+
+    /// Remove and return the snapshot at i and everything newer. The
+    /// first element is the one to revert to; the rest are its
+    /// descendants, to be killed.
+    pub fn split_off(&mut self, i: uint) -> ~[Snapshot] {
+        let mut tail = ~[];
+        while self.list.len() > i {
+            tail.push(self.list.pop());
+        }
+        tail.reverse();
+        tail
+    }
+
+    /// Remove all snapshots, returning their pids.
+    pub fn clear(&mut self) -> ~[pid_t] {
+        let pids = self.list.iter().map(|s| s.pid).collect();
+        self.list = ~[];
+        pids
+    }
+}
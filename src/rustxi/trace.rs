@@ -0,0 +1,393 @@
+/**
+ *  trace: external effects, the part of a failed snippet that rollback
+ *  cannot reach.
+ *
+ *  Forking rolls back memory, not the world: bytes written to a file,
+ *  a socket or a pipe, files created, renamed or removed, processes
+ *  started or signalled all stay done when TRY dies. So CUR traces TRY
+ *  with ptrace, stopping it at each syscall, and writes down every one
+ *  of those that succeeds. If TRY fails, the list travels to VISOR in
+ *  the failed reply and the user sees what was not undone.
+ *
+ *  On the way it notes whether TRY was refused memory, so a crash that
+ *  follows can be put down to the memory limit.
+ *
+ *  TRY's output on stdout and stderr, and its messages to VISOR, are
+ *  not counted. Nor is anything done by a process TRY starts: we trace
+ *  TRY alone (no PTRACE_O_TRACEFORK), so the report says the process
+ *  was started and that it was not traced. A TRY that succeeds must be let go before it becomes
+ *  CUR, since its tracer is about to be killed or stopped. It says so
+ *  by writing to a pipe of its own, the done fd; the tracer detaches
+ *  at that write and answers, and TRY waits for the answer before it
+ *  goes on as CUR.
+ *
+ *  The sandbox doesn't allow ptrace, so a sandboxed TRY, which would
+ *  one day have to trace its own TRY, is never traced.
+ **/
+
+use std::{libc, os, ptr, vec};
+use std::libc::{c_int, c_void};
+use std::libc::types::os::arch::posix88::{pid_t};
+
+use crash;
+use signum;
+use util;
+
+pub mod ll {
+    use std::libc::{c_char, c_int, c_long, c_void, size_t, ssize_t};
+    use std::libc::types::os::arch::posix88::{pid_t};
+
+    // x86_64 struct user_regs_struct
+    pub struct user_regs {
+        r15: u64, r14: u64, r13: u64, r12: u64, rbp: u64, rbx: u64,
+        r11: u64, r10: u64, r9: u64, r8: u64, rax: u64, rcx: u64,
+        rdx: u64, rsi: u64, rdi: u64, orig_rax: u64, rip: u64, cs: u64,
+        eflags: u64, rsp: u64, ss: u64, fs_base: u64, gs_base: u64,
+        ds: u64, es: u64, fs: u64, gs: u64,
+    }
+
+    pub static PTRACE_PEEKDATA: c_int = 2;
+    pub static PTRACE_GETREGS: c_int = 12;
+    pub static PTRACE_DETACH: c_int = 17;
+    pub static PTRACE_SYSCALL: c_int = 24;
+    pub static PTRACE_SEIZE: c_int = 0x4206;
+    pub static PTRACE_INTERRUPT: c_int = 0x4207;
+    pub static PTRACE_LISTEN: c_int = 0x4208;
+
+    pub static PTRACE_O_TRACESYSGOOD: uint = 1;
+    pub static PTRACE_O_TRACEEXEC: uint = 0x10;
+    pub static PTRACE_EVENT_STOP: c_int = 128;
+
+    // x86_64 syscall numbers of the effects we look for
+    pub static SYS_WRITE: u64 = 1;
+    pub static SYS_OPEN: u64 = 2;
+    pub static SYS_MMAP: u64 = 9;
+    pub static SYS_MREMAP: u64 = 25;
+    pub static SYS_PWRITE64: u64 = 18;
+    pub static SYS_WRITEV: u64 = 20;
+    pub static SYS_CONNECT: u64 = 42;
+    pub static SYS_SENDTO: u64 = 44;
+    pub static SYS_SENDMSG: u64 = 46;
+    pub static SYS_CLONE: u64 = 56;
+    pub static SYS_FORK: u64 = 57;
+    pub static SYS_VFORK: u64 = 58;
+    pub static SYS_EXECVE: u64 = 59;
+    pub static SYS_KILL: u64 = 62;
+    pub static SYS_TRUNCATE: u64 = 76;
+    pub static SYS_FTRUNCATE: u64 = 77;
+    pub static SYS_RENAME: u64 = 82;
+    pub static SYS_MKDIR: u64 = 83;
+    pub static SYS_RMDIR: u64 = 84;
+    pub static SYS_CREAT: u64 = 85;
+    pub static SYS_UNLINK: u64 = 87;
+    pub static SYS_TGKILL: u64 = 234;
+    pub static SYS_OPENAT: u64 = 257;
+    pub static SYS_MKDIRAT: u64 = 258;
+    pub static SYS_UNLINKAT: u64 = 263;
+    pub static SYS_RENAMEAT: u64 = 264;
+    pub static SYS_PWRITEV: u64 = 296;
+    pub static SYS_CLONE3: u64 = 435;
+
+    pub static O_WRONLY: u64 = 1;
+    pub static O_RDWR: u64 = 2;
+    pub static O_CREAT: u64 = 0o100;
+    pub static O_TRUNC: u64 = 0o1000;
+
+    pub static AT_REMOVEDIR: u64 = 0x200;
+
+    pub static ENOMEM: i64 = 12;
+
+    extern {
+        pub fn ptrace(request: c_int, pid: pid_t, addr: *c_void, data: *c_void) -> c_long;
+        pub fn readlink(path: *c_char, buf: *mut c_char, size: size_t) -> ssize_t;
+    }
+}
+
+// a syscall seen on entry, to be judged on exit.
+struct Pending {
+    /// the effect, if the syscall succeeds; written at entry, while
+    /// its fds and paths still mean what they did.
+    effect: Option<~str>,
+    /// for syscalls whose effect depends on the result: the write
+    /// family (bytes written) and open (the fd it returned).
+    fill: Fill,
+}
+
+enum Fill {
+    Fixed,
+    Bytes(~str, ~str),
+    OpenedFd(~str),
+}
+
+pub struct Tracer {
+    pid: pid_t,
+    /// fds whose writes are not effects: stdio and VISOR's pipes.
+    quiet: ~[c_int],
+    /// TRY writes here when it has succeeded.
+    done_fd: c_int,
+    /// let go of TRY when it crashes, so gdb can attach (crash.rs).
+    release_crashes: bool,
+    pending: Option<Pending>,
+    effects: ~[~str],
+    /// an mmap or mremap failed for want of memory.
+    alloc_failed: bool,
+}
+
+impl Tracer {
+    /// Start tracing TRY. None if we may not, e.g. because of the
+    /// yama ptrace_scope setting; TRY then runs untraced.
+    #[fixed_stack_segment]
+    pub fn attach(pid: pid_t, quiet: ~[c_int], done_fd: c_int,
+                  release_crashes: bool) -> Option<Tracer> {
+        unsafe {
+            if ll::ptrace(ll::PTRACE_SEIZE, pid, ptr::null(),
+                          (ll::PTRACE_O_TRACESYSGOOD | ll::PTRACE_O_TRACEEXEC) as *c_void) != 0 {
+                debug2!("{:d}: CUR: cannot trace TRY {:d}: {:s}",
+                       util::getpid() as int, pid as int, os::last_os_error());
+                return None;
+            }
+            ll::ptrace(ll::PTRACE_INTERRUPT, pid, ptr::null(), ptr::null());
+        }
+        Some(Tracer { pid: pid, quiet: quiet, done_fd: done_fd, release_crashes: release_crashes,
+                      pending: None, effects: ~[], alloc_failed: false })
+    }
+
+    /// Wait for TRY to exit or die, as waitpid would, noting effects
+    /// along the way. on_stop is called whenever TRY is stopped by job
+    /// control, and on_release once TRY is let go after succeeding.
+    /// Returns TRY's wait status, the effects it had, and whether it
+    /// was refused memory.
+    #[fixed_stack_segment]
+    pub fn wait(self, on_stop: &fn(), on_release: &fn()) -> (c_int, ~[~str], bool) {
+        let mut t = self;
+        let mut status = 0 as c_int;
+        loop {
+            util::waitpid(t.pid, &mut status);
+            let sig = match util::stop_signal(status) {
+                None => break,
+                Some(sig) => sig,
+            };
+            let event = (status >> 16) & 0xff;
+            let resume_with = if sig == signum::SIGTRAP | 0x80 {
+                if t.syscall_stop() {
+                    // TRY succeeded; let it go and wait the usual way.
+                    unsafe { ll::ptrace(ll::PTRACE_DETACH, t.pid, ptr::null(), ptr::null()); }
+                    on_release();
+                    return (wait_untraced(t.pid, on_stop), t.effects, t.alloc_failed);
+                }
+                0
+            } else if event != 0 {
+                if event == ll::PTRACE_EVENT_STOP && is_job_stop(sig) {
+                    // a group stop: TRY really is stopped now. LISTEN
+                    // leaves it stopped until SIGCONT.
+                    if is_terminal_stop(sig) {
+                        on_stop();
+                    }
+                    unsafe { ll::ptrace(ll::PTRACE_LISTEN, t.pid, ptr::null(), ptr::null()); }
+                    continue;
+                }
+                // our PTRACE_INTERRUPT, the end of a group stop, or
+                // a successful exec.
+                0
+            } else if t.release_crashes && crash::is_crash(sig) {
+                // detach, delivering the signal: TRY's handler runs gdb.
+                unsafe { ll::ptrace(ll::PTRACE_DETACH, t.pid, ptr::null(), sig as *c_void); }
+                return (wait_untraced(t.pid, on_stop), t.effects, t.alloc_failed);
+            } else {
+                // a signal on its way to TRY: deliver it.
+                sig
+            };
+            unsafe {
+                ll::ptrace(ll::PTRACE_SYSCALL, t.pid, ptr::null(), resume_with as *c_void);
+            }
+        }
+        (status, t.effects, t.alloc_failed)
+    }
+
+    // at a syscall entry or exit. True when TRY signals it is done.
+    fn syscall_stop(&mut self) -> bool {
+        let regs = match self.regs() {
+            None => return false,
+            Some(r) => r,
+        };
+        match ::std::util::replace(&mut self.pending, None) {
+            Some(Pending { effect, fill }) => {
+                // syscall exit: rax is the result, negative for errno.
+                let ret = regs.rax as i64;
+                if ret == -ll::ENOMEM && (regs.orig_rax == ll::SYS_MMAP
+                                          || regs.orig_rax == ll::SYS_MREMAP) {
+                    self.alloc_failed = true;
+                }
+                if ret >= 0 {
+                    let effect = match fill {
+                        Fixed => effect,
+                        Bytes(verb, what) => Some(format!("{:s} {:d} bytes to {:s}",
+                                                          verb, ret as int, what)),
+                        OpenedFd(verb) => Some(format!("{:s} {:s}", verb,
+                                                       self.fd_path(ret as u64))),
+                    };
+                    match effect {
+                        Some(e) => self.effects.push(e),
+                        None => (),
+                    }
+                }
+                false
+            },
+            None => {
+                if regs.orig_rax == ll::SYS_WRITE && regs.rdi == self.done_fd as u64 {
+                    return true;
+                }
+                self.pending = Some(self.entry(&regs));
+                false
+            },
+        }
+    }
+
+    // at syscall entry: what it would do if it succeeded.
+    fn entry(&self, regs: &ll::user_regs) -> Pending {
+        let nr = regs.orig_rax;
+        let (a0, a1, a2) = (regs.rdi, regs.rsi, regs.rdx);
+        let mut effect = None;
+        let mut fill = Fixed;
+        if nr == ll::SYS_WRITE || nr == ll::SYS_PWRITE64 || nr == ll::SYS_WRITEV
+            || nr == ll::SYS_PWRITEV {
+            if !self.quiet.contains(&(a0 as c_int)) {
+                fill = Bytes(~"wrote", self.fd_path(a0));
+            }
+        } else if nr == ll::SYS_SENDTO || nr == ll::SYS_SENDMSG {
+            fill = Bytes(~"sent", self.fd_path(a0));
+        } else if nr == ll::SYS_CONNECT {
+            effect = Some(format!("connected {:s}", self.fd_path(a0)));
+        } else if nr == ll::SYS_OPEN || nr == ll::SYS_OPENAT || nr == ll::SYS_CREAT {
+            let flags = if nr == ll::SYS_CREAT {
+                ll::O_WRONLY | ll::O_CREAT | ll::O_TRUNC
+            } else if nr == ll::SYS_OPENAT {
+                a2
+            } else {
+                a1
+            };
+            if flags & ll::O_TRUNC != 0 && flags & (ll::O_WRONLY | ll::O_RDWR) != 0 {
+                fill = OpenedFd(~"truncated");
+            } else if flags & ll::O_CREAT != 0 {
+                fill = OpenedFd(~"created or opened");
+            }
+        } else if nr == ll::SYS_TRUNCATE {
+            effect = Some(format!("truncated {:s}", self.string_at(a0)));
+        } else if nr == ll::SYS_FTRUNCATE {
+            effect = Some(format!("truncated {:s}", self.fd_path(a0)));
+        } else if nr == ll::SYS_UNLINK {
+            effect = Some(format!("removed {:s}", self.string_at(a0)));
+        } else if nr == ll::SYS_UNLINKAT {
+            let what = if a2 & ll::AT_REMOVEDIR != 0 { "removed directory" } else { "removed" };
+            effect = Some(format!("{:s} {:s}", what, self.string_at(a1)));
+        } else if nr == ll::SYS_RMDIR {
+            effect = Some(format!("removed directory {:s}", self.string_at(a0)));
+        } else if nr == ll::SYS_MKDIR {
+            effect = Some(format!("made directory {:s}", self.string_at(a0)));
+        } else if nr == ll::SYS_MKDIRAT {
+            effect = Some(format!("made directory {:s}", self.string_at(a1)));
+        } else if nr == ll::SYS_RENAME {
+            effect = Some(format!("renamed {:s} to {:s}", self.string_at(a0), self.string_at(a1)));
+        } else if nr == ll::SYS_RENAMEAT {
+            effect = Some(format!("renamed {:s} to {:s}", self.string_at(a1),
+                                  self.string_at(regs.r10)));
+        } else if nr == ll::SYS_CLONE || nr == ll::SYS_FORK || nr == ll::SYS_VFORK
+            || nr == ll::SYS_CLONE3 {
+            // its own effects go unseen: we trace TRY alone, not what it forks.
+            effect = Some(~"started a process (not traced)");
+        } else if nr == ll::SYS_EXECVE {
+            effect = Some(format!("executed {:s}", self.string_at(a0)));
+        } else if nr == ll::SYS_KILL || nr == ll::SYS_TGKILL {
+            let (target, sig) = if nr == ll::SYS_KILL { (a0, a1) } else { (a1, a2) };
+            if target as pid_t != self.pid && sig != 0 {
+                effect = Some(format!("sent signal {:d} to process {:d}",
+                                      sig as int, target as pid_t as int));
+            }
+        }
+        Pending { effect: effect, fill: fill }
+    }
+
+    #[fixed_stack_segment]
+    fn regs(&self) -> Option<ll::user_regs> {
+        unsafe {
+            let mut regs: ll::user_regs = ::std::unstable::intrinsics::init();
+            let p: *mut ll::user_regs = &mut regs;
+            if ll::ptrace(ll::PTRACE_GETREGS, self.pid, ptr::null(), p as *c_void) != 0 {
+                return None;
+            }
+            Some(regs)
+        }
+    }
+
+    // what TRY's fd refers to: a path, or e.g. socket:[1234].
+    #[fixed_stack_segment]
+    fn fd_path(&self, fd: u64) -> ~str {
+        let link = format!("/proc/{:d}/fd/{:u}", self.pid as int, fd as uint);
+        let mut buf = vec::from_elem(4096, 0u8);
+        let n = do link.with_c_str |p| {
+            do buf.as_mut_buf |b, len| {
+                unsafe { ll::readlink(p, b as *mut libc::c_char, len as libc::size_t) }
+            }
+        };
+        if n <= 0 {
+            return format!("fd {:u}", fd as uint);
+        }
+        util::lossy(buf.slice_to(n as uint))
+    }
+
+    // a NUL-terminated string in TRY's memory, e.g. a path argument.
+    #[fixed_stack_segment]
+    fn string_at(&self, addr: u64) -> ~str {
+        let mut bytes = ~[];
+        let mut at = addr;
+        while bytes.len() < 4096 {
+            // -1 is also a word of data; only errno says it failed.
+            util::clear_errno();
+            let word = unsafe {
+                ll::ptrace(ll::PTRACE_PEEKDATA, self.pid, at as *c_void, ptr::null())
+            };
+            if word == -1 && os::errno() != 0 {
+                break;
+            }
+            let mut end = false;
+            for i in range(0, 8) {
+                let b = ((word as u64) >> (8 * i)) as u8;
+                if b == 0 {
+                    end = true;
+                    break;
+                }
+                bytes.push(b);
+            }
+            if end {
+                break;
+            }
+            at += 8;
+        }
+        util::lossy(bytes)
+    }
+}
+
+fn is_job_stop(sig: c_int) -> bool {
+    sig == signum::SIGSTOP || is_terminal_stop(sig)
+}
+
+// ctrl-z, or a background read or write on the terminal. A plain
+// SIGSTOP is TRY keeping itself for post-mortem, which VISOR already
+// knows about.
+fn is_terminal_stop(sig: c_int) -> bool {
+    sig == signum::SIGTSTP || sig == signum::SIGTTIN || sig == signum::SIGTTOU
+}
+
+/// Wait for an untraced TRY to exit or die, calling on_stop each time
+/// it is stopped from the terminal instead.
+pub fn wait_untraced(pid: pid_t, on_stop: &fn()) -> c_int {
+    let mut status = 0 as c_int;
+    loop {
+        util::waitpid_untraced(pid, &mut status);
+        match util::stop_signal(status) {
+            None => return status,
+            Some(sig) if is_terminal_stop(sig) => on_stop(),
+            Some(_) => (),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+/**
+ *  twice: the .twice dry-run check for nondeterministic snippets.
+ *
+ *  With .twice on, the snippet runs in two sibling TRYs forked from
+ *  the same CUR, one after the other. The TRY that reads it from VISOR
+ *  hands it back to CUR without running any of it; CUR forks a twin to
+ *  run it to completion, tells VISOR how that went and what external
+ *  effects it left, then forks the TRY that runs it for real. Only that
+ *  one can be committed. Both print into the capture pipes to VISOR,
+ *  which hides the twin's output, passes TRY's through, and compares
+ *  the two runs: output and outcome.
+ *  Code that reads the clock, uses randomness or prints addresses
+ *  shows up as a divergence.
+ **/
+
+use std::str;
+
+use capture::Output;
+
+/// How the two runs differed; empty if they agree.
+pub fn compare(twin_ok: bool, twin_out: &Output, ok: bool, out: &Output) -> ~[~str] {
+    let mut diffs = ~[];
+    if twin_ok != ok {
+        diffs.push(format!("the first run {:s}, the second {:s}",
+                           if twin_ok { "succeeded" } else { "failed" },
+                           if ok { "succeeded" } else { "failed" }));
+    }
+    compare_stream("stdout", twin_out.out, out.out, &mut diffs);
+    compare_stream("stderr", twin_out.err, out.err, &mut diffs);
+    diffs
+}
+
+// the first line where a and b differ, if they do.
+fn compare_stream(name: &str, a: &[u8], b: &[u8], diffs: &mut ~[~str]) {
+    if a == b {
+        return;
+    }
+    let mut la = a.split_iter(|&c| c == '\n' as u8);
+    let mut lb = b.split_iter(|&c| c == '\n' as u8);
+    let mut lineno = 0u;
+    loop {
+        lineno += 1;
+        match (la.next(), lb.next()) {
+            (Some(x), Some(y)) if x == y => continue,
+            (x, y) => {
+                diffs.push(format!("{:s} differs at line {:u}:", name, lineno));
+                diffs.push(format!("  first:  {:s}", show(x)));
+                diffs.push(format!("  second: {:s}", show(y)));
+                break;
+            },
+        }
+    }
+}
+
+fn show(line: Option<&[u8]>) -> ~str {
+    match line {
+        None => ~"<end of output>",
+        Some(l) if str::is_utf8(l) => str::from_utf8(l),
+        Some(l) => format!("<{:u} bytes, not utf-8>", l.len()),
+    }
+}
@@ -1,4 +1,4 @@
-use std::{libc, os, str};
+use std::{cast, libc, os, str};
 use std::libc::{c_int, c_void};
 use std::libc::types::os::arch::posix88::{pid_t};
 
@@ -7,21 +7,47 @@ mod signum;
 #[nolink]
 #[abi = "cdecl"]
 pub mod ll {
-    use std::libc::{c_int, c_void, c_schar};
+    use std::libc::{c_int, c_uint, c_ulong, c_void, c_schar, c_short};
     use std::libc::types::os::arch::posix88::{pid_t};
     use std::libc::types::common::c95::{FILE};
 
+    pub struct pollfd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    pub static POLLIN: c_short = 1;
+
+    // glibc's struct sigaction on x86_64
+    pub struct sigaction {
+        sa_sigaction: *c_void,
+        sa_mask: [u64, ..16],
+        sa_flags: c_int,
+        sa_restorer: *c_void,
+    }
+
+    pub static PR_SET_PDEATHSIG: c_int = 1;
+    pub static PR_SET_CHILD_SUBREAPER: c_int = 36;
+
     extern {
         pub fn kill(pid: pid_t, sig: c_int) -> c_int;
+        pub fn __errno_location() -> *mut c_int;
         pub fn getsid(pid: pid_t) -> c_int;
         pub fn getpgrp() -> c_int;
         pub fn setpgid(pid: pid_t, pgid: pid_t) -> c_int;
         pub fn signal(signum: c_int, handler: *c_void);
+        pub fn sigaction(sig: c_int, act: *sigaction, old: *mut sigaction) -> c_int;
         pub fn rust_unset_sigprocmask();
         pub fn clearerr(fd : *FILE);
         pub fn getenv(name: *mut c_schar) -> *c_schar;
         pub fn setenv(name: *c_schar, value: *c_schar, overwrite: c_int) -> c_int;
         pub fn unsetenv(name: *c_schar) -> c_int;
+        pub fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+        pub fn usleep(usec: c_uint) -> c_int;
+        pub fn prctl(option: c_int, arg2: c_ulong, ...) -> c_int;
+        pub fn tcgetpgrp(fd: c_int) -> pid_t;
+        pub fn tcsetpgrp(fd: c_int, pgrp: pid_t) -> c_int;
     }
 }
 
@@ -31,6 +57,12 @@ pub fn waitpid(pid: libc::pid_t, status: &mut libc::c_int) -> libc::pid_t {
     unsafe { libc::funcs::posix01::wait::waitpid(pid, status, 0) }
 }
 
+// like waitpid, but also return when pid stops (WUNTRACED).
+#[fixed_stack_segment]
+pub fn waitpid_untraced(pid: libc::pid_t, status: &mut libc::c_int) -> libc::pid_t {
+    unsafe { libc::funcs::posix01::wait::waitpid(pid, status, 2) }
+}
+
 #[fixed_stack_segment]
 pub fn waitpid_async(pid: libc::pid_t, status: &mut libc::c_int) -> libc::pid_t {
     unsafe { libc::funcs::posix01::wait::waitpid(pid, status, 1) }
@@ -56,11 +88,59 @@ pub fn getpgrp() -> libc::pid_t {
     unsafe { ll::getpgrp() }
 }
 
+#[fixed_stack_segment]
+pub fn setpgid(pid: libc::pid_t, pgid: libc::pid_t) -> libc::c_int {
+    unsafe { ll::setpgid(pid, pgid) }
+}
+
+// have the kernel send us sig when our parent dies; 0 turns it off.
+#[fixed_stack_segment]
+pub fn set_parent_death_signal(sig: libc::c_int) {
+    unsafe { ll::prctl(ll::PR_SET_PDEATHSIG, sig as libc::c_ulong); }
+}
+
+// orphans among our descendants become our children, not init's.
+#[fixed_stack_segment]
+pub fn set_child_subreaper() -> bool {
+    unsafe { ll::prctl(ll::PR_SET_CHILD_SUBREAPER, 1 as libc::c_ulong) == 0 }
+}
+
+// reap every child that has exited, without blocking.
+pub fn reap_exited() {
+    let mut status = 0 as libc::c_int;
+    while waitpid_async(-1, &mut status) > 0 {}
+}
+
+#[fixed_stack_segment]
+pub fn tcgetpgrp(fd: libc::c_int) -> libc::pid_t {
+    unsafe { ll::tcgetpgrp(fd) }
+}
+
+#[fixed_stack_segment]
+pub fn tcsetpgrp(fd: libc::c_int, pgrp: libc::pid_t) -> libc::c_int {
+    unsafe { ll::tcsetpgrp(fd, pgrp) }
+}
+
+#[fixed_stack_segment]
+pub fn isatty(fd: libc::c_int) -> bool {
+    unsafe { libc::isatty(fd) != 0 }
+}
+
 #[fixed_stack_segment]
 pub fn ignore_sigint() {
     unsafe { ll::signal(signum::SIGINT, signum::SIG_IGN); }
 }
 
+#[fixed_stack_segment]
+pub fn ignore_signal(sig: libc::c_int) {
+    unsafe { ll::signal(sig, signum::SIG_IGN); }
+}
+
+#[fixed_stack_segment]
+pub fn default_signal(sig: libc::c_int) {
+    unsafe { ll::signal(sig, signum::SIG_DFL); }
+}
+
 #[fixed_stack_segment]
 pub fn deliver_sigint() {
     unsafe { ll::signal(signum::SIGINT, signum::SIG_DFL); }
@@ -75,6 +155,30 @@ pub fn copy_buf_to_string(buf: *mut u8, len: uint) -> ~str {
     unsafe { str::raw::from_buf_len(buf as *u8, len) }
 }
 
+// bytes from outside (paths, another program's output) as a string,
+// with \xNN for each byte that isn't ASCII if they aren't valid utf-8.
+pub fn lossy(bytes: &[u8]) -> ~str {
+    if str::is_utf8(bytes) {
+        return str::from_utf8(bytes);
+    }
+    let mut s = ~"";
+    for &b in bytes.iter() {
+        if b < 0x80 {
+            s.push_char(b as char);
+        } else {
+            s.push_str(format!("\\x{:02x}", b as uint));
+        }
+    }
+    s
+}
+
+// for calls whose every return value is valid, where only errno tells
+// a failure apart.
+#[fixed_stack_segment]
+pub fn clear_errno() {
+    unsafe { *ll::__errno_location() = 0; }
+}
+
 #[cfg(unix)]
 #[fixed_stack_segment]
 pub fn fork() -> libc::pid_t {
@@ -102,11 +206,78 @@ pub fn write(fd: libc::c_int, buf: *libc::c_void, count: libc::size_t) -> libc::
     unsafe { libc::write(fd, buf, count) }
 }
 
-pub fn process_group_exit() -> ! {
-    #[fixed_stack_segment]; #[inline(never)];
-    unsafe { 
-        // send SIGTERM to all processes in my process group
-        ll::kill(0, libc::SIGTERM);
-        libc::exit(0); 
+// WIFEXITED and WEXITSTATUS: the exit status, if the process exited.
+pub fn exit_status(status: libc::c_int) -> Option<libc::c_int> {
+    if status & 0x7f == 0 { Some((status >> 8) & 0xff) } else { None }
+}
+
+// WIFSIGNALED and WTERMSIG: the signal, if the process was killed by one.
+pub fn term_signal(status: libc::c_int) -> Option<libc::c_int> {
+    let sig = status & 0x7f;
+    if sig != 0 && sig != 0x7f { Some(sig) } else { None }
+}
+
+// WIFSTOPPED and WSTOPSIG: the signal, if the process was stopped.
+pub fn stop_signal(status: libc::c_int) -> Option<libc::c_int> {
+    if status & 0xff == 0x7f { Some((status >> 8) & 0xff) } else { None }
+}
+
+// read one byte from fd, blocking; None at EOF or on error.
+pub fn read_byte(fd: libc::c_int) -> Option<u8> {
+    let mut b = 0u8;
+    if read(fd, unsafe { cast::transmute(&mut b) }, 1) == 1 { Some(b) } else { None }
+}
+
+pub fn write_byte(fd: libc::c_int, b: u8) -> bool {
+    write(fd, unsafe { cast::transmute(&b) }, 1) == 1
+}
+
+// throw away whatever is waiting to be read on fd.
+pub fn drain(fd: libc::c_int) {
+    while poll_readable(fd, 0) && read_byte(fd).is_some() {}
+}
+
+// wait up to timeout_ms for fd to become readable (or hit EOF).
+#[fixed_stack_segment]
+pub fn poll_readable(fd: libc::c_int, timeout_ms: uint) -> bool {
+    let mut p = ll::pollfd { fd: fd, events: ll::POLLIN, revents: 0 };
+    unsafe { ll::poll(&mut p, 1, timeout_ms as libc::c_int) > 0 }
+}
+
+// block until one of fds is readable; which of them are.
+#[fixed_stack_segment]
+pub fn poll_any(fds: &[libc::c_int]) -> ~[bool] {
+    let mut p: ~[ll::pollfd] = fds.iter().map(|&fd| {
+        ll::pollfd { fd: fd, events: ll::POLLIN, revents: 0 }
+    }).collect();
+    let n = do p.as_mut_buf |ptr, len| {
+        unsafe { ll::poll(ptr, len as libc::c_ulong, -1) }
+    };
+    if n <= 0 {
+        return fds.map(|_| false);
+    }
+    p.map(|q| q.revents != 0)
+}
+
+#[fixed_stack_segment]
+pub fn sleep_ms(ms: uint) {
+    unsafe { ll::usleep((ms * 1000) as libc::c_uint); }
+}
+
+// wait up to timeout_ms for pid to be gone, reaping it if it is our
+// child. True if it went away in time.
+pub fn wait_gone(pid: libc::pid_t, timeout_ms: uint) -> bool {
+    let mut waited = 0u;
+    loop {
+        let mut status = 0 as libc::c_int;
+        waitpid_async(pid, &mut status);
+        if kill(pid, 0) != 0 {
+            return true;
+        }
+        if waited >= timeout_ms {
+            return false;
+        }
+        sleep_ms(10);
+        waited += 10;
     }
 }